@@ -0,0 +1,111 @@
+//! Round-trips of the host file operations
+//!
+//! Runs on QEMU, from the root of this crate:
+//!
+//! ``` text
+//! $ cargo run --example hio --target thumbv7m-none-eabi
+//! ```
+//!
+//! The files are created in the working directory of QEMU, and removed
+//! again. The program exits with a failure status if any check fails.
+
+#![no_main]
+#![no_std]
+
+#[macro_use]
+extern crate cortex_m_semihosting;
+extern crate cortex_m_rt as rt;
+extern crate panic_halt;
+
+use cortex_m_semihosting::debug::{self, EXIT_FAILURE, EXIT_SUCCESS};
+use cortex_m_semihosting::hio::{self, Error, HostFile};
+use cortex_m_semihosting::nr;
+use rt::entry;
+
+const NAME: &str = "hio-example.txt";
+const RENAMED: &str = "hio-example-renamed.txt";
+const DATA: &[u8] = b"Hello, host file!\n";
+
+#[entry]
+fn main() -> ! {
+    match run() {
+        Ok(()) => {
+            hprintln!("all checks passed").ok();
+            debug::exit(EXIT_SUCCESS);
+        }
+        Err(e) => {
+            heprintln!("failed: {:?}", e).ok();
+            debug::exit(EXIT_FAILURE);
+        }
+    }
+
+    loop {}
+}
+
+fn run() -> Result<(), Error> {
+    let mut buffer = [0; 128];
+    let cmdline = hio::cmdline(&mut buffer)?;
+    hprintln!("command line: {}", cmdline)?;
+
+    // open, write, len
+    let mut file = HostFile::open(NAME, nr::open::RW_TRUNC_BINARY)?;
+    file.write_all(DATA)?;
+    check(file.len()? == DATA.len(), "length after write");
+
+    // seek, read
+    let mut read = [0; 64];
+    file.seek(7)?;
+    file.read_exact(&mut read[..4])?;
+    check(&read[..4] == b"host", "read after seek");
+
+    file.seek(0)?;
+    let n = file.read(&mut read)?;
+    check(&read[..n] == DATA, "read of the whole file");
+    check(file.read(&mut read)? == 0, "read at the end of the file");
+    file.close()?;
+
+    // rename, then the old name is gone
+    hio::rename(NAME, RENAMED)?;
+    check(
+        HostFile::open(NAME, nr::open::R_BINARY).is_err(),
+        "open of the old name",
+    );
+
+    let mut file = HostFile::open(RENAMED, nr::open::R_BINARY)?;
+    check(file.len()? == DATA.len(), "length after rename");
+    file.read_exact(&mut read[..DATA.len()])?;
+    check(&read[..DATA.len()] == DATA, "read after rename");
+    file.close()?;
+
+    // remove
+    hio::remove(RENAMED)?;
+    check(
+        HostFile::open(RENAMED, nr::open::R_BINARY).is_err(),
+        "open of a removed file",
+    );
+
+    // tmpnam, the same id gives the same name
+    let mut first = [0; 64];
+    let mut second = [0; 64];
+    let name = hio::tmpnam(&mut first, 1)?;
+    check(
+        name == hio::tmpnam(&mut second, 1)?,
+        "tmpnam of the same id",
+    );
+
+    let mut file = HostFile::open(name, nr::open::W_TRUNC_BINARY)?;
+    file.write_all(DATA)?;
+    check(file.len()? == DATA.len(), "length of a temporary file");
+    file.close()?;
+    hio::remove(name)?;
+
+    Ok(())
+}
+
+/// Exits with a failure status unless `ok`
+fn check(ok: bool, what: &str) {
+    if !ok {
+        heprintln!("check failed: {}", what).ok();
+        debug::exit(EXIT_FAILURE);
+    }
+}
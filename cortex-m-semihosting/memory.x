@@ -0,0 +1,6 @@
+/* LM3S6965, as emulated by QEMU's lm3s6965evb machine, for the examples */
+MEMORY
+{
+  FLASH : ORIGIN = 0x00000000, LENGTH = 256K
+  RAM : ORIGIN = 0x20000000, LENGTH = 64K
+}
@@ -4,48 +4,121 @@ use core::fmt::{self, Write};
 
 use cortex_m::interrupt;
 
-use hio::{self, HStderr, HStdout};
+use hio::{self, Error, HStderr, HStdout};
+use host;
 
 static mut HSTDOUT: Option<HStdout> = None;
 
-pub fn hstdout_str(s: &str) -> Result<(), ()> {
+pub fn hstdout_str(s: &str) -> Result<(), Error> {
     interrupt::free(|_| unsafe {
         if HSTDOUT.is_none() {
             HSTDOUT = Some(hio::hstdout()?);
         }
 
-        HSTDOUT.as_mut().unwrap().write_str(s).map_err(drop)
+        HSTDOUT.as_mut().unwrap().write_all(s.as_bytes())
     })
 }
 
-pub fn hstdout_fmt(args: fmt::Arguments) -> Result<(), ()> {
+pub fn hstdout_fmt(args: fmt::Arguments) -> Result<(), Error> {
     interrupt::free(|_| unsafe {
         if HSTDOUT.is_none() {
             HSTDOUT = Some(hio::hstdout()?);
         }
 
-        HSTDOUT.as_mut().unwrap().write_fmt(args).map_err(drop)
+        let hstdout = HSTDOUT.as_mut().unwrap();
+        write_fmt(|bytes| hstdout.write_all(bytes), args)
     })
 }
 
 static mut HSTDERR: Option<HStderr> = None;
 
-pub fn hstderr_str(s: &str) -> Result<(), ()> {
+pub fn hstderr_str(s: &str) -> Result<(), Error> {
     interrupt::free(|_| unsafe {
         if HSTDERR.is_none() {
             HSTDERR = Some(hio::hstderr()?);
         }
 
-        HSTDERR.as_mut().unwrap().write_str(s).map_err(drop)
+        HSTDERR.as_mut().unwrap().write_all(s.as_bytes())
     })
 }
 
-pub fn hstderr_fmt(args: fmt::Arguments) -> Result<(), ()> {
+pub fn hstderr_fmt(args: fmt::Arguments) -> Result<(), Error> {
     interrupt::free(|_| unsafe {
         if HSTDERR.is_none() {
             HSTDERR = Some(hio::hstderr()?);
         }
 
-        HSTDERR.as_mut().unwrap().write_fmt(args).map_err(drop)
+        let hstderr = HSTDERR.as_mut().unwrap();
+        write_fmt(|bytes| hstderr.write_all(bytes), args)
     })
 }
+
+pub fn detect_hstdout_str(s: &str) -> Result<(), Error> {
+    if !host::available() {
+        return to_sink(s.as_bytes());
+    }
+
+    hstdout_str(s)
+}
+
+pub fn detect_hstdout_fmt(args: fmt::Arguments) -> Result<(), Error> {
+    if !host::available() {
+        return write_fmt(to_sink, args);
+    }
+
+    hstdout_fmt(args)
+}
+
+pub fn detect_hstderr_str(s: &str) -> Result<(), Error> {
+    if !host::available() {
+        return to_sink(s.as_bytes());
+    }
+
+    hstderr_str(s)
+}
+
+pub fn detect_hstderr_fmt(args: fmt::Arguments) -> Result<(), Error> {
+    if !host::available() {
+        return write_fmt(to_sink, args);
+    }
+
+    hstderr_fmt(args)
+}
+
+/// Passes output the host cannot take to the sink, if any
+fn to_sink(bytes: &[u8]) -> Result<(), Error> {
+    if let Some(sink) = host::sink() {
+        sink(bytes);
+    }
+    Ok(())
+}
+
+/// Formats `args` with `write`, keeping the error of a failed write, which
+/// `fmt::Write` would reduce to a `fmt::Error`
+fn write_fmt<F>(write: F, args: fmt::Arguments) -> Result<(), Error>
+where
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    let mut adapter = Adapter { write, error: None };
+    match adapter.write_fmt(args) {
+        Ok(()) => Ok(()),
+        Err(fmt::Error) => Err(adapter.error.unwrap_or(Error::Format)),
+    }
+}
+
+struct Adapter<F> {
+    write: F,
+    error: Option<Error>,
+}
+
+impl<F> Write for Adapter<F>
+where
+    F: FnMut(&[u8]) -> Result<(), Error>,
+{
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        (self.write)(s.as_bytes()).map_err(|e| {
+            self.error = Some(e);
+            fmt::Error
+        })
+    }
+}
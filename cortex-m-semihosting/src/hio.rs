@@ -1,8 +1,41 @@
 //! Host I/O
+//!
+//! Besides the host's standard output and error, files on the host can be
+//! opened as a `HostFile`, to read input data from or to dump logs to. The
+//! names are relative to the working directory of the debugger, or of QEMU
+//! when run with `-semihosting`:
+//!
+//! ``` no_run
+//! use cortex_m_semihosting::hio::{self, HostFile};
+//! use cortex_m_semihosting::nr;
+//!
+//! fn checksum() -> Result<u8, hio::Error> {
+//!     let mut file = HostFile::open("vectors.bin", nr::open::R_BINARY)?;
+//!
+//!     let mut sum = 0u8;
+//!     let mut buf = [0; 64];
+//!     loop {
+//!         let n = file.read(&mut buf)?;
+//!         if n == 0 {
+//!             break;
+//!         }
+//!         sum = buf[..n].iter().fold(sum, |s, b| s.wrapping_add(*b));
+//!     }
+//!
+//!     file.close()?;
+//!     Ok(sum)
+//! }
+//! ```
+
+use core::{fmt, mem, str};
+
+use cortex_m::interrupt;
 
-use core::{fmt, slice};
 use nr;
 
+/// Size of the buffer used to NUL terminate file names
+const NAME_BUFFER_SIZE: usize = 256;
+
 /// Host's standard error
 pub struct HStderr {
     fd: usize,
@@ -10,7 +43,7 @@ pub struct HStderr {
 
 impl HStderr {
     /// Attempts to write an entire `buffer` into this sink
-    pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), ()> {
+    pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), Error> {
         write_all(self.fd, buffer)
     }
 }
@@ -28,7 +61,7 @@ pub struct HStdout {
 
 impl HStdout {
     /// Attempts to write an entire `buffer` into this sink
-    pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), ()> {
+    pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), Error> {
         write_all(self.fd, buffer)
     }
 }
@@ -39,8 +72,72 @@ impl fmt::Write for HStdout {
     }
 }
 
+/// Host's standard input
+pub struct HStdin {
+    fd: usize,
+}
+
+impl HStdin {
+    /// Reads into `buffer`, returning the number of bytes read
+    ///
+    /// Blocks until the host has input, usually a whole line from its
+    /// terminal. Returns zero at the end of the input.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        read(self.fd, buffer)
+    }
+
+    /// Reads a line into `buffer`, returning its length
+    ///
+    /// The line includes its terminating newline, unless the input ended
+    /// first, or the line did not fit `buffer`, in which case the rest of it
+    /// is returned by the next call.
+    pub fn read_line(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut len = 0;
+        while len < buffer.len() {
+            let n = self.read(&mut buffer[len..])?;
+            if n == 0 {
+                break;
+            }
+            len += n;
+            if buffer[len - n..len].contains(&b'\n') {
+                break;
+            }
+        }
+        Ok(len)
+    }
+}
+
+/// Construct a new handle to the host's standard input.
+pub fn hstdin() -> Result<HStdin, Error> {
+    open(":tt\0", nr::open::R).map(|fd| HStdin { fd })
+}
+
+/// Reads a byte from the host's terminal, blocking until one is typed
+pub fn readc() -> u8 {
+    unsafe { syscall!(READC) as u8 }
+}
+
+/// Reads the command line of the program, as given to the debugger or to
+/// QEMU with `-semihosting-config arg=..`, into `buffer`
+///
+/// The host writes the command line with a terminating NUL, and fails with
+/// `Error::Host` when `buffer` is too short to hold both, without writing
+/// part of it.
+pub fn cmdline(buffer: &mut [u8]) -> Result<&str, Error> {
+    // The host updates the length in the argument block
+    let mut args = [buffer.as_mut_ptr() as usize, buffer.len()];
+    match unsafe { ::syscall1(nr::GET_CMDLINE, args.as_mut_ptr() as usize) } {
+        0 => {
+            let len = args[1].min(buffer.len());
+            let len = buffer[..len].iter().position(|b| *b == 0).unwrap_or(len);
+            str::from_utf8(&buffer[..len]).map_err(|_| Error::NotUtf8)
+        }
+        _ => Err(host_error()),
+    }
+}
+
 /// Construct a new handle to the host's standard error.
-pub fn hstderr() -> Result<HStderr, ()> {
+pub fn hstderr() -> Result<HStderr, Error> {
     // There is actually no stderr access in ARM Semihosting documentation. Use
     // convention used in libgloss.
     // See: libgloss/arm/syscalls.c, line 139.
@@ -49,34 +146,287 @@ pub fn hstderr() -> Result<HStderr, ()> {
 }
 
 /// Construct a new handle to the host's standard output.
-pub fn hstdout() -> Result<HStdout, ()> {
+pub fn hstdout() -> Result<HStdout, Error> {
     open(":tt\0", nr::open::W_TRUNC).map(|fd| HStdout { fd })
 }
 
-fn open(name: &str, mode: usize) -> Result<usize, ()> {
+fn open(name: &str, mode: usize) -> Result<usize, Error> {
     let name = name.as_bytes();
     match unsafe { syscall!(OPEN, name.as_ptr(), mode, name.len() - 1) } as
         isize {
-        -1 => Err(()),
+        -1 => Err(host_error()),
         fd => Ok(fd as usize),
     }
 }
 
-fn write_all(fd: usize, mut buffer: &[u8]) -> Result<(), ()> {
-    while !buffer.is_empty() {
-        match unsafe { syscall!(WRITE, fd, buffer.as_ptr(), buffer.len()) } {
+fn write_all(fd: usize, buffer: &[u8]) -> Result<(), Error> {
+    let mut written = 0;
+    while written < buffer.len() {
+        let rest = &buffer[written..];
+        match unsafe { syscall!(WRITE, fd, rest.as_ptr(), rest.len()) } {
             // Done
             0 => return Ok(()),
             // `n` bytes were not written
-            n if n <= buffer.len() => {
-                let offset = (buffer.len() - n) as isize;
-                buffer = unsafe {
-                    slice::from_raw_parts(buffer.as_ptr().offset(offset), n)
-                }
-            }
-            // Error - This should be error, but JLink does strange stuff...
-            _ => return Ok(()),
+            n if n < rest.len() => written += rest.len() - n,
+            // Nothing was written, or JLink wrote everything and returned
+            // the number of bytes written
+            n if n == rest.len() => match quirk_mode() {
+                QuirkMode::Lenient => return Ok(()),
+                // Retrying would loop forever
+                QuirkMode::Strict => return Err(Error::ShortWrite { written }),
+            },
+            // This should be an error, but JLink does strange stuff...
+            n => match quirk_mode() {
+                QuirkMode::Lenient => return Ok(()),
+                QuirkMode::Strict => return Err(Error::UnexpectedReturn(n)),
+            },
         }
     }
     Ok(())
 }
+
+/// Error of a semihosting operation
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The host reported an error, with the value of its C library `errno`
+    Host(usize),
+    /// The host stopped accepting data, after `written` bytes
+    ShortWrite {
+        /// Number of bytes written before the host stopped
+        written: usize,
+    },
+    /// The host returned a value the semihosting interface does not allow,
+    /// only reported in `QuirkMode::Strict`
+    UnexpectedReturn(usize),
+    /// A value being printed failed to format itself
+    Format,
+    /// A file name is too long or contains a NUL byte, or a temporary file
+    /// name from the host is not NUL terminated
+    InvalidName,
+    /// The command line or a temporary file name from the host is not valid
+    /// UTF-8
+    NotUtf8,
+    /// The end of the file was reached before the buffer was filled
+    UnexpectedEof,
+}
+
+/// How to treat replies of the debugger that break the semihosting
+/// interface
+///
+/// Some debuggers, J-Link among them, return the number of bytes written to
+/// the host's terminal where the number of bytes *not* written is expected,
+/// which looks like a failed write. A complete write then can't be told
+/// apart from one where nothing was written.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum QuirkMode {
+    /// Take such writes to the host's standard output and error as
+    /// successful, the default
+    Lenient,
+    /// Report them as `Error::ShortWrite` when nothing appears to be
+    /// written, and as `Error::UnexpectedReturn` otherwise, to tell a
+    /// misbehaving debugger apart from a working one
+    Strict,
+}
+
+static mut QUIRK_MODE: QuirkMode = QuirkMode::Lenient;
+
+/// The current `QuirkMode`
+pub fn quirk_mode() -> QuirkMode {
+    interrupt::free(|_| unsafe { QUIRK_MODE })
+}
+
+/// Sets how replies that break the semihosting interface are treated
+pub fn set_quirk_mode(mode: QuirkMode) {
+    interrupt::free(|_| unsafe { QUIRK_MODE = mode })
+}
+
+/// The `errno` of the host, which is only set after a failed operation
+pub fn errno() -> usize {
+    unsafe { syscall!(ERRNO) }
+}
+
+fn host_error() -> Error {
+    Error::Host(errno())
+}
+
+/// A file on the host
+///
+/// The file is closed when dropped, use `close` to learn whether closing
+/// succeeded.
+pub struct HostFile {
+    fd: usize,
+}
+
+impl HostFile {
+    /// Opens the file `name` in `mode`, one of the `nr::open` modes
+    pub fn open(name: &str, mode: usize) -> Result<HostFile, Error> {
+        with_c_name(name, |name, len| {
+            match unsafe { syscall!(OPEN, name.as_ptr(), mode, len) } as isize {
+                -1 => Err(host_error()),
+                fd => Ok(HostFile { fd: fd as usize }),
+            }
+        })?
+    }
+
+    /// The handle of the file on the host
+    pub fn fd(&self) -> usize {
+        self.fd
+    }
+
+    /// Reads into `buffer`, returning the number of bytes read
+    ///
+    /// Fewer bytes than requested are read at the end of the file, and none
+    /// once it has been reached.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, Error> {
+        read(self.fd, buffer)
+    }
+
+    /// Fills the whole `buffer`
+    pub fn read_exact(&mut self, mut buffer: &mut [u8]) -> Result<(), Error> {
+        while !buffer.is_empty() {
+            match self.read(buffer)? {
+                0 => return Err(Error::UnexpectedEof),
+                n => buffer = &mut mem::replace(&mut buffer, &mut [])[n..],
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes from `buffer`, returning the number of bytes written
+    pub fn write(&mut self, buffer: &[u8]) -> Result<usize, Error> {
+        // The host returns the number of bytes *not* written
+        match unsafe { syscall!(WRITE, self.fd, buffer.as_ptr(), buffer.len()) }
+        {
+            n if n <= buffer.len() => Ok(buffer.len() - n),
+            _ => Err(host_error()),
+        }
+    }
+
+    /// Writes the whole `buffer`
+    pub fn write_all(&mut self, buffer: &[u8]) -> Result<(), Error> {
+        let mut written = 0;
+        while written < buffer.len() {
+            match self.write(&buffer[written..])? {
+                0 => return Err(Error::ShortWrite { written }),
+                n => written += n,
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves to the absolute `position`, in bytes from the start of the file
+    pub fn seek(&mut self, position: usize) -> Result<(), Error> {
+        match unsafe { syscall!(SEEK, self.fd, position) } {
+            0 => Ok(()),
+            _ => Err(host_error()),
+        }
+    }
+
+    /// The length of the file in bytes
+    pub fn len(&self) -> Result<usize, Error> {
+        match unsafe { syscall!(FLEN, self.fd) } as isize {
+            -1 => Err(host_error()),
+            len => Ok(len as usize),
+        }
+    }
+
+    /// Is the file empty?
+    pub fn is_empty(&self) -> Result<bool, Error> {
+        self.len().map(|len| len == 0)
+    }
+
+    /// Is the file an interactive device, such as the host's terminal?
+    pub fn is_tty(&self) -> bool {
+        unsafe { syscall!(ISTTY, self.fd) == 1 }
+    }
+
+    /// Closes the file
+    pub fn close(self) -> Result<(), Error> {
+        let fd = self.fd;
+        mem::forget(self);
+
+        match unsafe { syscall!(CLOSE, fd) } {
+            0 => Ok(()),
+            _ => Err(host_error()),
+        }
+    }
+}
+
+impl Drop for HostFile {
+    fn drop(&mut self) {
+        unsafe {
+            syscall!(CLOSE, self.fd);
+        }
+    }
+}
+
+impl fmt::Write for HostFile {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_all(s.as_bytes()).map_err(|_| fmt::Error)
+    }
+}
+
+fn read(fd: usize, buffer: &mut [u8]) -> Result<usize, Error> {
+    // The host returns the number of bytes *not* read
+    match unsafe { syscall!(READ, fd, buffer.as_mut_ptr(), buffer.len()) } {
+        n if n <= buffer.len() => Ok(buffer.len() - n),
+        _ => Err(host_error()),
+    }
+}
+
+/// Deletes the file `name` on the host
+pub fn remove(name: &str) -> Result<(), Error> {
+    with_c_name(name, |name, len| {
+        match unsafe { syscall!(REMOVE, name.as_ptr(), len) } {
+            0 => Ok(()),
+            errno => Err(Error::Host(errno)),
+        }
+    })?
+}
+
+/// Renames the file `from` to `to` on the host
+pub fn rename(from: &str, to: &str) -> Result<(), Error> {
+    with_c_name(from, |from, from_len| {
+        with_c_name(to, |to, to_len| {
+            match unsafe {
+                syscall!(RENAME, from.as_ptr(), from_len, to.as_ptr(), to_len)
+            } {
+                0 => Ok(()),
+                errno => Err(Error::Host(errno)),
+            }
+        })?
+    })?
+}
+
+/// Asks the host for the name of a temporary file, written to `buffer`
+///
+/// Names are numbered by `id`, the same `id` giving the same name as long
+/// as the file exists. The file is not created.
+pub fn tmpnam(buffer: &mut [u8], id: u8) -> Result<&str, Error> {
+    match unsafe { syscall!(TMPNAM, buffer.as_mut_ptr(), id, buffer.len()) } {
+        0 => {
+            let len = buffer
+                .iter()
+                .position(|b| *b == 0)
+                .ok_or(Error::InvalidName)?;
+            str::from_utf8(&buffer[..len]).map_err(|_| Error::NotUtf8)
+        }
+        _ => Err(host_error()),
+    }
+}
+
+/// Calls `f` with `name` NUL terminated, and its length without the NUL
+fn with_c_name<F, R>(name: &str, f: F) -> Result<R, Error>
+where
+    F: FnOnce(&[u8], usize) -> R,
+{
+    let name = name.as_bytes();
+    if name.len() >= NAME_BUFFER_SIZE || name.contains(&0) {
+        return Err(Error::InvalidName);
+    }
+
+    let mut buffer = [0; NAME_BUFFER_SIZE];
+    buffer[..name.len()].copy_from_slice(name);
+
+    Ok(f(&buffer, name.len()))
+}
@@ -0,0 +1,111 @@
+//! Detection of the host
+//!
+//! Semihosting operations are `bkpt` instructions handled by the debugger.
+//! Without a debugger attached the `bkpt` escalates to a HardFault, so
+//! firmware that prints with `hprintln!` crashes once it runs without a
+//! probe. A debugger sets C_DEBUGEN in the Debug Halting Control and Status
+//! Register (DHCSR) while it is attached, which is what `debugger_attached`
+//! checks. DHCSR is part of the debug architecture of both ARMv6-M and
+//! ARMv7-M, see the ARMv6-M and ARMv7-M Architecture Reference Manuals.
+//!
+//! The `dprint` family of macros, `dprint!`, `dprintln!`, `deprint!` and
+//! `deprintln!`, follows the `Policy` set with `set_policy`. Unless the host
+//! is `available`, they pass their output to the sink set with `set_sink`,
+//! or drop it if there is none. The `hprint` family of macros and the `hio`
+//! handles always issue semihosting operations:
+//!
+//! ``` no_run
+//! #[macro_use]
+//! extern crate cortex_m_semihosting;
+//! use cortex_m_semihosting::host::{self, Policy};
+//!
+//! fn uart_write(bytes: &[u8]) {
+//!     // ..
+//! }
+//!
+//! fn init() {
+//!     host::set_policy(Policy::Detect);
+//!     host::set_sink(Some(uart_write));
+//!
+//!     // Goes to the debugger when attached, to the UART otherwise
+//!     dprintln!("Hello, world!").ok();
+//! }
+//! ```
+//!
+//! The default policy is `Policy::Always`, or `Policy::Detect` with the
+//! `detect-debugger` feature, so the `dprint` macros become no-ops in the
+//! field without any change to the firmware.
+
+use cortex_m::interrupt;
+
+/// Address of the Debug Halting Control and Status Register
+#[cfg(thumb)]
+const DHCSR: *const u32 = 0xE000_EDF0 as *const u32;
+
+/// Set in DHCSR while a debugger is attached
+#[cfg(thumb)]
+const DHCSR_C_DEBUGEN: u32 = 1 << 0;
+
+/// When semihosting operations are issued
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Policy {
+    /// Always, a HardFault without a debugger
+    Always,
+    /// Only while a debugger is attached
+    Detect,
+    /// Never, the output of the `dprint` family of macros goes to the sink
+    Never,
+}
+
+#[cfg(not(feature = "detect-debugger"))]
+const DEFAULT_POLICY: Policy = Policy::Always;
+#[cfg(feature = "detect-debugger")]
+const DEFAULT_POLICY: Policy = Policy::Detect;
+
+/// Receives the output of the `dprint` family of macros when the host is
+/// not available
+pub type Sink = fn(&[u8]);
+
+static mut POLICY: Policy = DEFAULT_POLICY;
+static mut SINK: Option<Sink> = None;
+
+/// Is a debugger attached?
+pub fn debugger_attached() -> bool {
+    match () {
+        #[cfg(thumb)]
+        () => unsafe { DHCSR.read_volatile() & DHCSR_C_DEBUGEN != 0 },
+
+        #[cfg(not(thumb))]
+        () => false,
+    }
+}
+
+/// The current `Policy`
+pub fn policy() -> Policy {
+    interrupt::free(|_| unsafe { POLICY })
+}
+
+/// Sets when semihosting operations are issued
+pub fn set_policy(policy: Policy) {
+    interrupt::free(|_| unsafe { POLICY = policy })
+}
+
+/// The current `Sink`
+pub fn sink() -> Option<Sink> {
+    interrupt::free(|_| unsafe { SINK })
+}
+
+/// Sets where the output of the `dprint` family of macros goes when the
+/// host is not available, `None` to drop it
+pub fn set_sink(sink: Option<Sink>) {
+    interrupt::free(|_| unsafe { SINK = sink })
+}
+
+/// Can semihosting operations be issued, according to the `Policy`?
+pub fn available() -> bool {
+    match policy() {
+        Policy::Always => true,
+        Policy::Detect => debugger_attached(),
+        Policy::Never => false,
+    }
+}
@@ -39,7 +39,7 @@
 //! fn print() -> Result<(), core::fmt::Error> {
 //!     let mut stdout = match hio::hstdout() {
 //!         Ok(fd) => fd,
-//!         Err(()) => return Err(core::fmt::Error),
+//!         Err(_) => return Err(core::fmt::Error),
 //!     };
 //!
 //!     let language = "Rust";
@@ -133,6 +133,12 @@
 //! When this feature is disabled semihosting is implemented using FFI calls into an external
 //! assembly file and compiling this crate works on stable and beta.
 //!
+//! ## `detect-debugger`
+//!
+//! When this feature is enabled the `dprint` family of macros only issues semihosting operations
+//! while a debugger is attached, and is a no-op otherwise, instead of hard-faulting. See the
+//! `host` module. The `hprint` family of macros is not affected.
+//!
 //! # Reference
 //!
 //! For documentation about the semihosting operations, check:
@@ -156,7 +162,9 @@ pub mod debug;
 #[doc(hidden)]
 pub mod export;
 pub mod hio;
+pub mod host;
 pub mod nr;
+pub mod time;
 
 #[cfg(all(thumb, not(feature = "inline-asm")))]
 extern "C" {
@@ -30,7 +30,7 @@ macro_rules! syscall1 {
 
 /// Macro for printing to the HOST standard output
 ///
-/// This macro returns a `Result<(), ()>` value
+/// This macro returns a `Result<(), hio::Error>` value
 #[macro_export]
 macro_rules! hprint {
     ($s:expr) => {
@@ -43,7 +43,7 @@ macro_rules! hprint {
 
 /// Macro for printing to the HOST standard output, with a newline.
 ///
-/// This macro returns a `Result<(), ()>` value
+/// This macro returns a `Result<(), hio::Error>` value
 #[macro_export]
 macro_rules! hprintln {
     () => {
@@ -59,7 +59,7 @@ macro_rules! hprintln {
 
 /// Macro for printing to the HOST standard error
 ///
-/// This macro returns a `Result<(), ()>` value
+/// This macro returns a `Result<(), hio::Error>` value
 #[macro_export]
 macro_rules! heprint {
     ($s:expr) => {
@@ -72,7 +72,7 @@ macro_rules! heprint {
 
 /// Macro for printing to the HOST standard error, with a newline.
 ///
-/// This macro returns a `Result<(), ()>` value
+/// This macro returns a `Result<(), hio::Error>` value
 #[macro_export]
 macro_rules! heprintln {
     () => {
@@ -85,3 +85,71 @@ macro_rules! heprintln {
         $crate::export::hstderr_fmt(format_args!(concat!($s, "\n"), $($tt)*))
     };
 }
+
+/// Macro for printing to the HOST standard output, if it is available
+///
+/// Like `hprint!`, but following the `host::Policy`: without a host the
+/// output goes to the `host::Sink`, or is dropped if there is none, instead
+/// of hard-faulting.
+///
+/// This macro returns a `Result<(), hio::Error>` value
+#[macro_export]
+macro_rules! dprint {
+    ($s:expr) => {
+        $crate::export::detect_hstdout_str($s)
+    };
+    ($($tt:tt)*) => {
+        $crate::export::detect_hstdout_fmt(format_args!($($tt)*))
+    };
+}
+
+/// Macro for printing to the HOST standard output, with a newline, if it is
+/// available
+///
+/// See `dprint!`. This macro returns a `Result<(), hio::Error>` value
+#[macro_export]
+macro_rules! dprintln {
+    () => {
+        $crate::export::detect_hstdout_str("\n")
+    };
+    ($s:expr) => {
+        $crate::export::detect_hstdout_str(concat!($s, "\n"))
+    };
+    ($s:expr, $($tt:tt)*) => {
+        $crate::export::detect_hstdout_fmt(
+            format_args!(concat!($s, "\n"), $($tt)*)
+        )
+    };
+}
+
+/// Macro for printing to the HOST standard error, if it is available
+///
+/// See `dprint!`. This macro returns a `Result<(), hio::Error>` value
+#[macro_export]
+macro_rules! deprint {
+    ($s:expr) => {
+        $crate::export::detect_hstderr_str($s)
+    };
+    ($($tt:tt)*) => {
+        $crate::export::detect_hstderr_fmt(format_args!($($tt)*))
+    };
+}
+
+/// Macro for printing to the HOST standard error, with a newline, if it is
+/// available
+///
+/// See `dprint!`. This macro returns a `Result<(), hio::Error>` value
+#[macro_export]
+macro_rules! deprintln {
+    () => {
+        $crate::export::detect_hstderr_str("\n")
+    };
+    ($s:expr) => {
+        $crate::export::detect_hstderr_str(concat!($s, "\n"))
+    };
+    ($s:expr, $($tt:tt)*) => {
+        $crate::export::detect_hstderr_fmt(
+            format_args!(concat!($s, "\n"), $($tt)*)
+        )
+    };
+}
@@ -0,0 +1,97 @@
+//! Host time and memory layout queries
+//!
+//! # Example
+//!
+//! This example times a function against the host's clock, for instance
+//! under QEMU with semihosting enabled.
+//!
+//! ``` no_run
+//! #[macro_use]
+//! extern crate cortex_m_semihosting;
+//! use cortex_m_semihosting::hio::Error;
+//! use cortex_m_semihosting::time;
+//!
+//! fn benchmark<F: FnMut()>(mut f: F) -> Result<(), Error> {
+//!     let freq = time::tickfreq()?;
+//!     let start = time::elapsed()?;
+//!     f();
+//!     let ticks = time::elapsed()? - start;
+//!
+//!     hprintln!("{} us", ticks * 1_000_000 / u64::from(freq)).ok();
+//!     Ok(())
+//! }
+//! ```
+//!
+//! `ELAPSED` and `TICKFREQ` are extensions of the semihosting interface,
+//! version 2.0, which not every debugger supports.
+
+use hio::{self, Error};
+use nr;
+
+/// Centiseconds since the program started, by the host's clock
+pub fn clock() -> Result<u32, Error> {
+    match unsafe { syscall!(CLOCK) } as isize {
+        -1 => Err(Error::Host(hio::errno())),
+        cs => Ok(cs as u32),
+    }
+}
+
+/// Seconds since the Unix epoch, 1970-01-01 00:00:00 UTC, by the host's
+/// wall clock
+pub fn time() -> Result<u32, Error> {
+    match unsafe { syscall!(TIME) } as isize {
+        -1 => Err(Error::Host(hio::errno())),
+        s => Ok(s as u32),
+    }
+}
+
+/// Ticks since the program started, see `tickfreq` for their frequency
+pub fn elapsed() -> Result<u64, Error> {
+    // The host writes the count to the argument block, low word first
+    let mut ticks = [0u32; 2];
+    match unsafe { ::syscall1(nr::ELAPSED, ticks.as_mut_ptr() as usize) } {
+        0 => Ok(u64::from(ticks[1]) << 32 | u64::from(ticks[0])),
+        _ => Err(Error::Host(hio::errno())),
+    }
+}
+
+/// Frequency of the ticks returned by `elapsed`, in Hz
+pub fn tickfreq() -> Result<u32, Error> {
+    match unsafe { syscall!(TICKFREQ) } as isize {
+        -1 => Err(Error::Host(hio::errno())),
+        hz => Ok(hz as u32),
+    }
+}
+
+/// Memory layout of the program, as known to the host
+///
+/// Fields the host does not know about are zero.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct HeapInfo {
+    /// Lowest address of the heap
+    pub heap_base: usize,
+    /// Highest address of the heap
+    pub heap_limit: usize,
+    /// Highest address of the stack, where it starts
+    pub stack_base: usize,
+    /// Lowest address of the stack
+    pub stack_limit: usize,
+}
+
+/// Memory layout of the program, as configured in the debugger or QEMU
+pub fn heapinfo() -> Result<HeapInfo, Error> {
+    // The argument block holds the address of the block the host fills in
+    let mut info = [0usize; 4];
+    let mut args = [info.as_mut_ptr() as usize];
+    match unsafe { ::syscall1(nr::HEAPINFO, args.as_mut_ptr() as usize) }
+        as isize
+    {
+        -1 => Err(Error::Host(hio::errno())),
+        _ => Ok(HeapInfo {
+            heap_base: info[0],
+            heap_limit: info[1],
+            stack_base: info[2],
+            stack_limit: info[3],
+        }),
+    }
+}
@@ -0,0 +1,116 @@
+//! app10.rs
+//!
+//! Lets blink a LED, this time scheduled on the RTC instead of the
+//! DWT cycle counter.
+//! The `schedule` API of RTFM uses the DWT cycle counter, which
+//! wraps every ~67 s at 64 MHz and stops when the CPU sleeps.
+//! RTFM 0.4 can not use another clock for its timer queue, so
+//! the tasks are scheduled on a `TimerQueue` kept on a
+//! `Monotonic` clock on RTC1 instead. The RTC1 interrupt spawns
+//! them once they are due, also from low-power sleep.
+
+#![no_main]
+#![no_std]
+
+// panic handler
+extern crate panic_semihosting;
+
+use cortex_m_semihosting::hprintln;
+use dwm1001::nrf52832_hal as hal;
+use embedded_hal::digital::OutputPin;
+use hal::gpio;
+use hal::gpio::p0::*;
+use hal::gpio::*;
+use hal::monotonic::{Duration, Instant, Monotonic, TimerQueue};
+use hal::prelude::*;
+use hal::target::RTC1;
+use heapless::consts::U4;
+use rtfm::app;
+
+// half a period in milliseconds
+const HALF_PERIOD_MS: u64 = 1_000;
+
+// The tasks scheduled on the RTC, with the instant they are
+// scheduled at
+pub enum Task {
+    Toggle(Instant),
+}
+
+#[app(device = crate::hal::target)]
+const APP: () = {
+    // Late resources
+    static mut LED: P0_14<gpio::Output<PushPull>> = ();
+    static mut MONO: Monotonic<RTC1> = ();
+    static mut QUEUE: TimerQueue<Task, U4> = ();
+
+    #[init]
+    fn init() -> init::LateResources {
+        hprintln!("init").unwrap();
+
+        // the RTC runs on the low frequency clock
+        device.CLOCK.constrain().start_lfclk();
+
+        let port0 = device.P0.split();
+        let led = port0.p0_14.into_push_pull_output(Level::High);
+
+        let mut mono = Monotonic::new(device.RTC1.constrain());
+        let mut queue = TimerQueue::new();
+        let next = mono.now() + Duration::from_millis(HALF_PERIOD_MS);
+        queue
+            .schedule(&mut mono, next, Task::Toggle(next))
+            .ok()
+            .unwrap();
+
+        init::LateResources {
+            LED: led,
+            MONO: mono,
+            QUEUE: queue,
+        }
+    }
+
+    #[idle]
+    fn idle() -> ! {
+        loop {
+            // sleep, the RTC keeps counting
+            cortex_m::asm::wfi();
+        }
+    }
+
+    // the spawned tasks run at the priority of the RTC1 interrupt,
+    // so they share MONO and QUEUE without locks
+    #[task(resources = [LED, MONO, QUEUE])]
+    fn toggle(scheduled: Instant) {
+        toggle_led(resources.LED);
+
+        // schedule relative to the previous instant, so there is no drift
+        let next = scheduled + Duration::from_millis(HALF_PERIOD_MS);
+        resources
+            .QUEUE
+            .schedule(resources.MONO, next, Task::Toggle(next))
+            .ok()
+            .unwrap();
+    }
+
+    #[interrupt(resources = [MONO, QUEUE], spawn = [toggle])]
+    fn RTC1() {
+        if resources.MONO.process_interrupt() {
+            while let Some(task) = resources.QUEUE.dequeue(resources.MONO) {
+                match task {
+                    Task::Toggle(scheduled) => spawn.toggle(scheduled).unwrap(),
+                }
+            }
+        }
+    }
+
+    extern "C" {
+        fn SWI1_EGU1();
+    }
+};
+
+fn toggle_led(led: &mut P0_14<Output<PushPull>>) {
+    if led.is_set_high() {
+        led.set_low();
+    } else {
+        led.set_high();
+    }
+}
@@ -7,32 +7,42 @@ extern crate panic_semihosting;
 use cortex_m_semihosting::hprintln;
 use dwm1001::{new_usb_uarte, nrf52832_hal as hal, UsbUarteConfig};
 
+use hal::ppi::{C0, C1};
 use hal::prelude::*;
-use hal::target::{interrupt, UARTE0};
-use hal::{DMAPool, RXError, TXQSize, UarteRX, UarteTX, DMA_SIZE};
+use hal::target::{interrupt, TIMER1, UARTE0};
+use hal::{Chunk, IdleTimeout, RXError, RXQSize, TXQSize, UarteRX, UarteTX};
 
 use heapless::{
-    pool::singleton::{Box, Pool},
+    pool,
+    pool::singleton::Pool,
     spsc::{Producer, Queue},
 };
 
 use rtfm::app;
 
+// DMA buffers large enough to take a line of input in one go. A buffer is
+// handed out when it is full, or when the line has been idle for a while.
+const DMA_SIZE: usize = 64;
+pool!(DMAPool: [u8; DMA_SIZE]);
+
 const NR_PACKAGES: usize = 10;
 const DMA_MEM: usize = DMA_SIZE * NR_PACKAGES + 16;
 
+// 1 ms at the 1 MHz of the timer, about 11 characters at 115200 baud
+const IDLE_CYCLES: u32 = 1_000;
+
 #[app(device = crate::hal::target)]
 const APP: () = {
-    static mut RX: UarteRX<UARTE0> = ();
-    static mut TX: UarteTX<UARTE0> = ();
-    static mut PRODUCER: Producer<'static, Box<DMAPool>, TXQSize> = ();
+    static mut RX: UarteRX<UARTE0, DMAPool, RXQSize, IdleTimeout<TIMER1, C0, C1>> = ();
+    static mut TX: UarteTX<UARTE0, DMAPool> = ();
+    static mut PRODUCER: Producer<'static, Chunk<DMAPool>, TXQSize> = ();
 
     #[init(spawn = [])]
     fn init() -> init::LateResources {
         // for the actual DMA buffers
         static mut MEMORY: [u8; DMA_MEM] = [0; DMA_MEM];
         // for the producer/consumer of TX
-        static mut TX_RB: Option<Queue<Box<DMAPool>, TXQSize>> = None;
+        static mut TX_RB: Option<Queue<Chunk<DMAPool>, TXQSize>> = None;
 
         hprintln!("init").unwrap();
         // move MEMORY to P (the DMA buffer allocator)
@@ -50,6 +60,13 @@ const APP: () = {
         let (txp, txc) = TX_RB.as_mut().unwrap().split();
         let (rx, tx) = uarte0.split(Queue::new(), txc);
 
+        // Hand out partially filled buffers once no byte has arrived for
+        // IDLE_CYCLES. The receiver takes the timer and PPI channels, they
+        // are given back by `disable_idle_timeout`.
+        let timer = device.TIMER1.constrain();
+        let ppi = device.PPI.split();
+        let rx = rx.enable_idle_timeout(timer, ppi.ppi0, ppi.ppi1, IDLE_CYCLES);
+
         init::LateResources {
             RX: rx,
             TX: tx,
@@ -57,12 +74,13 @@ const APP: () = {
         }
     }
 
-    // // we can get Box<P> us being now the owner
+    // we get the received Chunk<P>, now being the owner
     #[task(capacity = 2, resources = [PRODUCER])]
-    fn printer(data: Box<DMAPool>) {
+    fn printer(data: Chunk<DMAPool>) {
         // enqueue a test message
         // let mut b = DMAPool::alloc().unwrap().freeze();
         // b.copy_from_slice(&[0, 1, 2, 3]);
+        // let data = Chunk::full(b);
 
         // hprintln!("{:?}", &data).unwrap();
         // just do the buffer dance without copying
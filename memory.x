@@ -1,5 +1,10 @@
 MEMORY
 {
-    FLASH : ORIGIN = 0x00000000, LENGTH = 512K
-    RAM   : ORIGIN = 0x20000000, LENGTH = 64K
+    FLASH   : ORIGIN = 0x00000000, LENGTH = 504K
+    /* Reserved for persistent data, see `nvmc` and `kvstore` in the HAL */
+    STORAGE : ORIGIN = 0x0007E000, LENGTH = 8K
+    RAM     : ORIGIN = 0x20000000, LENGTH = 64K
 }
+
+_storage_start = ORIGIN(STORAGE);
+_storage_end = ORIGIN(STORAGE) + LENGTH(STORAGE);
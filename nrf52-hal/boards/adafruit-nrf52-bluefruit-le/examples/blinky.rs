@@ -65,7 +65,7 @@ fn delay<T>(timer: &mut Timer<T>, cycles: u32)
 where
     T: TimerExt,
 {
-    timer.start(cycles);
+    timer.start(cycles.us()); // 1 MHz timer, a cycle per microsecond
     block!(timer.wait()).unwrap();
 }
 
@@ -41,6 +41,6 @@ fn delay<T>(timer: &mut Timer<T>, cycles: u32)
 where
     T: TimerExt,
 {
-    timer.start(cycles);
+    timer.start(cycles.us()); // 1 MHz timer, a cycle per microsecond
     block!(timer.wait());
 }
@@ -1,7 +1,29 @@
 //! Configuration and control of the High and Low Frequency Clock
 //! sources
+//!
+//! Starting a clock takes from a fraction of a millisecond up to a quarter
+//! of a second for an external 32.768 kHz crystal. Besides the blocking
+//! `enable_ext_hfosc` and `start_lfclk`, the `_nb` variants only request the
+//! clock, whose start is then signalled by the HFCLKSTARTED and LFCLKSTARTED
+//! events and the CLOCK interrupt.
+//!
+//! The internal RC oscillator must be calibrated against the external high
+//! frequency crystal to stay within 500 ppm, at least every 8 seconds or
+//! when the temperature changes by 0.5 °C, see `LfrcCalibration`.
+//!
+//! Drivers needing the accuracy of the external high frequency crystal can
+//! share it through `Clocks::share_hfxo`: the crystal runs while at least
+//! one `HfxoRequest` is held, and the system falls back to the internal
+//! oscillator, which draws less current, once all of them are dropped.
+//! While shared, the high frequency clock source can only be changed through
+//! requests, until `Clocks::unshare_hfxo` takes back the last `Hfxo` handle.
 
 use crate::target::CLOCK;
+use crate::temp::Temp;
+use cortex_m::interrupt;
+use fpa::I30F2;
+use nb;
+use void::Void;
 
 // ZST Type States
 
@@ -11,6 +33,9 @@ pub struct Internal;
 /// External Crystal Oscillator
 pub struct ExternalOscillator;
 
+/// External Crystal Oscillator, started on request of `Hfxo` handles
+pub struct SharedHfxo;
+
 /// Low Frequency Clock synthesize from High Frequency Clock
 pub struct LfOscSynthesized;
 
@@ -30,6 +55,37 @@ pub const HFCLK_FREQ: u32 = 64_000_000;
 /// Low Frequency Clock Frequency (in Hz)
 pub const LFCLK_FREQ: u32 = 32_768;
 
+/// Source of the high frequency clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HfClkSource {
+    /// Internal 64 MHz RC oscillator
+    Rc,
+    /// External 64 MHz crystal oscillator
+    Xtal,
+}
+
+/// Source of the low frequency clock
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LfClkSource {
+    /// Internal 32.768 kHz RC oscillator
+    Rc,
+    /// External 32.768 kHz crystal oscillator
+    Xtal,
+    /// Synthesized from the high frequency clock
+    Synth,
+}
+
+/// Interrupts and events of the CLOCK peripheral
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ClocksInterrupt {
+    /// The external high frequency oscillator has started
+    HfClkStarted,
+    /// The low frequency clock has started
+    LfClkStarted,
+    /// The calibration of the low frequency RC oscillator is complete
+    Done,
+}
+
 /// A high level abstraction for the CLOCK peripheral
 pub struct Clocks<H, L, LSTAT> {
     hfclk: H,
@@ -39,51 +95,111 @@ pub struct Clocks<H, L, LSTAT> {
 }
 
 impl<H, L, LSTAT> Clocks<H, L, LSTAT> {
-    /// Use an external oscillator as the high frequency clock source
-    pub fn enable_ext_hfosc(self) -> Clocks<ExternalOscillator, L, LSTAT> {
-        self.periph.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+    /// Start the Low Frequency clock
+    pub fn start_lfclk(self) -> Clocks<H, L, LfOscStarted> {
+        let mut clocks = self.start_lfclk_nb();
 
-        // Datasheet says this is likely to take 0.36ms
-        while self.periph.events_hfclkstarted.read().bits() != 1 {}
-        self.periph
-            .events_hfclkstarted
-            .write(|w| unsafe { w.bits(0) });
+        // Datasheet says this could take 100us from synth source
+        // 600us from rc source, 0.25s from an external source
+        nb::block!(clocks.lfclk_started()).unwrap();
+
+        clocks
+    }
+
+    /// Request the Low Frequency clock, without waiting for it to start
+    ///
+    /// Peripherals clocked by it, such as the RTC, only count once it has
+    /// started, which is signalled by `lfclk_started` or the `LfClkStarted`
+    /// interrupt.
+    pub fn start_lfclk_nb(self) -> Clocks<H, L, LfOscStarted> {
+        self.periph.events_lfclkstarted.reset();
+        self.periph.tasks_lfclkstart.write(|w| unsafe { w.bits(1) });
 
         Clocks {
-            hfclk: ExternalOscillator,
+            hfclk: self.hfclk,
             lfclk: self.lfclk,
-            lfstat: self.lfstat,
+            lfstat: LfOscStarted,
             periph: self.periph,
         }
     }
 
-    /// Use the internal oscillator as the high frequency clock source
-    pub fn disable_ext_hfosc(self) -> Clocks<Internal, L, LSTAT> {
-        self.periph.tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
-        Clocks {
-            hfclk: Internal,
-            lfclk: self.lfclk,
-            lfstat: self.lfstat,
-            periph: self.periph,
+    /// Check whether the requested high frequency oscillator has started,
+    /// clearing the event
+    pub fn hfclk_started(&mut self) -> nb::Result<(), Void> {
+        if self.periph.events_hfclkstarted.read().bits() == 0 {
+            return Err(nb::Error::WouldBlock);
         }
+        self.periph.events_hfclkstarted.reset();
+        Ok(())
     }
 
-    /// Start the Low Frequency clock
-    pub fn start_lfclk(self) -> Clocks<H, L, LfOscStarted> {
-        self.periph.tasks_lfclkstart.write(|w| unsafe { w.bits(1) });
+    /// Check whether the requested low frequency clock has started,
+    /// clearing the event
+    pub fn lfclk_started(&mut self) -> nb::Result<(), Void> {
+        if self.periph.events_lfclkstarted.read().bits() == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+        self.periph.events_lfclkstarted.reset();
+        Ok(())
+    }
 
-        // Datasheet says this could take 100us from synth source
-        // 600us from rc source, 0.25s from an external source
-        while self.periph.events_lfclkstarted.read().bits() != 1 {}
-        self.periph
-            .events_lfclkstarted
-            .write(|w| unsafe { w.bits(0) });
+    /// The source the high frequency clock is running from, if it is running
+    pub fn hfclk_source(&self) -> Option<HfClkSource> {
+        let stat = self.periph.hfclkstat.read();
+        if stat.state().is_not_running() {
+            return None;
+        }
 
-        Clocks {
-            hfclk: self.hfclk,
-            lfclk: self.lfclk,
-            lfstat: LfOscStarted,
-            periph: self.periph,
+        if stat.src().is_xtal() {
+            Some(HfClkSource::Xtal)
+        } else {
+            Some(HfClkSource::Rc)
+        }
+    }
+
+    /// The source the low frequency clock is running from, if it is running
+    pub fn lfclk_source(&self) -> Option<LfClkSource> {
+        let stat = self.periph.lfclkstat.read();
+        if stat.state().is_not_running() {
+            return None;
+        }
+
+        if stat.src().is_xtal() {
+            Some(LfClkSource::Xtal)
+        } else if stat.src().is_synth() {
+            Some(LfClkSource::Synth)
+        } else {
+            Some(LfClkSource::Rc)
+        }
+    }
+
+    /// Enable the CLOCK interrupt for an event
+    pub fn enable_interrupt(&mut self, int: ClocksInterrupt) {
+        match int {
+            ClocksInterrupt::HfClkStarted => {
+                self.periph.intenset.write(|w| w.hfclkstarted().set())
+            }
+            ClocksInterrupt::LfClkStarted => {
+                self.periph.intenset.write(|w| w.lfclkstarted().set())
+            }
+            ClocksInterrupt::Done => {
+                self.periph.intenset.write(|w| w.done().set())
+            }
+        }
+    }
+
+    /// Disable the CLOCK interrupt for an event
+    pub fn disable_interrupt(&mut self, int: ClocksInterrupt) {
+        match int {
+            ClocksInterrupt::HfClkStarted => {
+                self.periph.intenclr.write(|w| w.hfclkstarted().clear())
+            }
+            ClocksInterrupt::LfClkStarted => {
+                self.periph.intenclr.write(|w| w.lfclkstarted().clear())
+            }
+            ClocksInterrupt::Done => {
+                self.periph.intenclr.write(|w| w.done().clear())
+            }
         }
     }
 }
@@ -160,6 +276,371 @@ impl<H, L> Clocks<H, L, LfOscStopped> {
     }
 }
 
+impl<L, LSTAT> Clocks<Internal, L, LSTAT> {
+    /// Use an external oscillator as the high frequency clock source
+    pub fn enable_ext_hfosc(self) -> Clocks<ExternalOscillator, L, LSTAT> {
+        let mut clocks = self.enable_ext_hfosc_nb();
+
+        // Datasheet says this is likely to take 0.36ms
+        nb::block!(clocks.hfclk_started()).unwrap();
+
+        clocks
+    }
+
+    /// Request the external oscillator as the high frequency clock source,
+    /// without waiting for it to start
+    ///
+    /// The internal oscillator keeps running until the external one has
+    /// started, which is signalled by `hfclk_started` or the `HfClkStarted`
+    /// interrupt.
+    pub fn enable_ext_hfosc_nb(self) -> Clocks<ExternalOscillator, L, LSTAT> {
+        self.periph.events_hfclkstarted.reset();
+        self.periph.tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+
+        Clocks {
+            hfclk: ExternalOscillator,
+            lfclk: self.lfclk,
+            lfstat: self.lfstat,
+            periph: self.periph,
+        }
+    }
+
+    /// Share the external high frequency oscillator between drivers
+    ///
+    /// The returned `Hfxo` handle can be cloned for every driver or task
+    /// needing the oscillator. The high frequency clock source can then only
+    /// be changed through requests.
+    ///
+    /// Sharing starts with no request held, whatever was left over from an
+    /// earlier sharing, e.g. a request passed to `mem::forget`.
+    pub fn share_hfxo(self) -> (Clocks<SharedHfxo, L, LSTAT>, Hfxo) {
+        interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            unsafe {
+                HFXO_HANDLES = 1;
+                HFXO_REQUESTS = 0;
+            }
+        });
+
+        let clocks = Clocks {
+            hfclk: SharedHfxo,
+            lfclk: self.lfclk,
+            lfstat: self.lfstat,
+            periph: self.periph,
+        };
+
+        (clocks, Hfxo { _private: () })
+    }
+}
+
+impl<L, LSTAT> Clocks<ExternalOscillator, L, LSTAT> {
+    /// Use the internal oscillator as the high frequency clock source
+    pub fn disable_ext_hfosc(self) -> Clocks<Internal, L, LSTAT> {
+        self.periph.tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
+        Clocks {
+            hfclk: Internal,
+            lfclk: self.lfclk,
+            lfstat: self.lfstat,
+            periph: self.periph,
+        }
+    }
+}
+
+impl<L, LSTAT> Clocks<SharedHfxo, L, LSTAT> {
+    /// Stop sharing the external high frequency oscillator
+    ///
+    /// `hfxo` must be the last `Hfxo` handle, and no request may be held, so
+    /// that the internal oscillator is in use. Otherwise the clocks and the
+    /// handle are given back.
+    pub fn unshare_hfxo(
+        self,
+        hfxo: Hfxo,
+    ) -> Result<Clocks<Internal, L, LSTAT>, (Self, Hfxo)> {
+        let last = interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            unsafe { HFXO_HANDLES == 1 && HFXO_REQUESTS == 0 }
+        });
+        if !last {
+            return Err((self, hfxo));
+        }
+        drop(hfxo);
+
+        Ok(Clocks {
+            hfclk: Internal,
+            lfclk: self.lfclk,
+            lfstat: self.lfstat,
+            periph: self.periph,
+        })
+    }
+}
+
+/// Number of held `HfxoRequest`s
+static mut HFXO_REQUESTS: u32 = 0;
+
+/// Number of existing `Hfxo` handles
+static mut HFXO_HANDLES: u32 = 0;
+
+/// Handle to the shared external high frequency oscillator
+///
+/// Handles are counted, so that `Clocks::unshare_hfxo` can tell when the
+/// last one is given back.
+pub struct Hfxo {
+    _private: (),
+}
+
+impl Clone for Hfxo {
+    fn clone(&self) -> Self {
+        interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            unsafe { HFXO_HANDLES += 1 };
+        });
+
+        Hfxo { _private: () }
+    }
+}
+
+impl Drop for Hfxo {
+    fn drop(&mut self) {
+        interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            unsafe { HFXO_HANDLES -= 1 };
+        });
+    }
+}
+
+impl Hfxo {
+    /// Request the oscillator, starting it if it is not running
+    ///
+    /// The oscillator keeps running until the returned request, and all
+    /// others, are dropped. It takes a fraction of a millisecond to start,
+    /// see `HfxoRequest::started`.
+    pub fn request(&self) -> HfxoRequest {
+        interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            let requests = unsafe { &mut HFXO_REQUESTS };
+            if *requests == 0 {
+                clock().tasks_hfclkstart.write(|w| unsafe { w.bits(1) });
+            }
+            *requests += 1;
+        });
+
+        HfxoRequest { _private: () }
+    }
+
+    /// Request the oscillator and wait for it to run
+    ///
+    /// Blocks for as long as the oscillator takes to start, use `request`
+    /// and `HfxoRequest::started` to wait with a timeout.
+    pub fn request_blocking(&self) -> HfxoRequest {
+        let request = self.request();
+
+        // Datasheet says this is likely to take 0.36ms
+        nb::block!(request.started()).unwrap();
+
+        request
+    }
+
+    /// Number of requests held
+    pub fn requests(&self) -> u32 {
+        // NOTE(unsafe) atomic read
+        interrupt::free(|_| unsafe { HFXO_REQUESTS })
+    }
+}
+
+/// A held request for the external high frequency oscillator, released
+/// when dropped
+pub struct HfxoRequest {
+    _private: (),
+}
+
+impl HfxoRequest {
+    /// Is the high frequency clock running from the external oscillator?
+    pub fn is_running(&self) -> bool {
+        let stat = clock().hfclkstat.read();
+        stat.state().is_running() && stat.src().is_xtal()
+    }
+
+    /// Check whether the external oscillator has started
+    pub fn started(&self) -> nb::Result<(), Void> {
+        if !self.is_running() {
+            return Err(nb::Error::WouldBlock);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for HfxoRequest {
+    fn drop(&mut self) {
+        interrupt::free(|_| {
+            // NOTE(unsafe) within a critical section
+            let requests = unsafe { &mut HFXO_REQUESTS };
+            *requests -= 1;
+            if *requests == 0 {
+                clock().tasks_hfclkstop.write(|w| unsafe { w.bits(1) });
+            }
+        });
+    }
+}
+
+/// The CLOCK registers, for the shared oscillator
+fn clock() -> &'static crate::target::clock::RegisterBlock {
+    // NOTE(unsafe) only the HFCLK tasks and status are used, which the
+    // `SharedHfxo` state leaves to `Hfxo` handles
+    unsafe { &*CLOCK::ptr() }
+}
+
+impl Clocks<ExternalOscillator, Internal, LfOscStarted> {
+    /// Start a calibration of the low frequency RC oscillator, without
+    /// waiting for it to complete
+    ///
+    /// Completion is signalled by `calibration_done` or the `Done`
+    /// interrupt. The external high frequency oscillator must have started.
+    pub fn start_calibration(&mut self) {
+        start_calibration(&self.periph);
+    }
+
+    /// Check whether the calibration is complete, clearing the event
+    pub fn calibration_done(&mut self) -> nb::Result<(), Void> {
+        calibration_done(&self.periph)
+    }
+
+    /// Calibrate the low frequency RC oscillator, blocking until complete
+    pub fn calibrate(&mut self) {
+        self.start_calibration();
+        nb::block!(self.calibration_done()).unwrap();
+    }
+}
+
+impl Clocks<SharedHfxo, Internal, LfOscStarted> {
+    /// Start a calibration of the low frequency RC oscillator, without
+    /// waiting for it to complete
+    ///
+    /// The calibration runs against the shared external oscillator, which
+    /// `request` keeps running until the calibration is complete. Nothing is
+    /// started while the oscillator is still starting, which is reported as
+    /// `WouldBlock`.
+    pub fn start_calibration(&mut self, request: &HfxoRequest) -> nb::Result<(), Void> {
+        request.started()?;
+        start_calibration(&self.periph);
+        Ok(())
+    }
+
+    /// Check whether the calibration is complete, clearing the event
+    pub fn calibration_done(&mut self) -> nb::Result<(), Void> {
+        calibration_done(&self.periph)
+    }
+
+    /// Calibrate the low frequency RC oscillator, blocking until complete
+    pub fn calibrate(&mut self, request: &HfxoRequest) {
+        nb::block!(self.start_calibration(request)).unwrap();
+        nb::block!(self.calibration_done()).unwrap();
+    }
+}
+
+fn start_calibration(periph: &CLOCK) {
+    periph.events_done.reset();
+    periph.tasks_cal.write(|w| unsafe { w.bits(1) });
+}
+
+fn calibration_done(periph: &CLOCK) -> nb::Result<(), Void> {
+    if periph.events_done.read().bits() == 0 {
+        return Err(nb::Error::WouldBlock);
+    }
+    periph.events_done.reset();
+    Ok(())
+}
+
+/// Periodic calibration of the low frequency RC oscillator
+///
+/// `tick` is meant to be called at a fixed interval, typically 4 seconds
+/// from an RTC. On each call the temperature is measured, and a calibration
+/// is started if it changed by 0.5 °C or more since the last calibration,
+/// or if `max_skip` calls in a row did not calibrate.
+pub struct LfrcCalibration {
+    temp: Temp,
+    /// Temperature at the last calibration
+    last: Option<I30F2>,
+    max_skip: u32,
+    skipped: u32,
+}
+
+impl LfrcCalibration {
+    /// Create the calibration routine, measuring temperature with `temp`
+    pub fn new(temp: Temp, max_skip: u32) -> Self {
+        LfrcCalibration {
+            temp,
+            last: None,
+            max_skip,
+            skipped: 0,
+        }
+    }
+
+    /// Return the temperature sensor
+    pub fn free(self) -> Temp {
+        self.temp
+    }
+
+    /// Start a calibration if needed, returning whether one was started
+    ///
+    /// The calibration completes in the background, see
+    /// `Clocks::calibration_done`.
+    pub fn tick(
+        &mut self,
+        clocks: &mut Clocks<ExternalOscillator, Internal, LfOscStarted>,
+    ) -> bool {
+        if !self.due() {
+            return false;
+        }
+
+        clocks.start_calibration();
+        true
+    }
+
+    /// Start a calibration if needed, against the shared external
+    /// oscillator, returning whether one was started
+    ///
+    /// `request` must be held until the calibration is complete, see
+    /// `Clocks::calibration_done`. While the oscillator is still starting
+    /// nothing is measured and `WouldBlock` is returned, so that the tick can
+    /// be retried.
+    pub fn tick_shared(
+        &mut self,
+        clocks: &mut Clocks<SharedHfxo, Internal, LfOscStarted>,
+        request: &HfxoRequest,
+    ) -> nb::Result<bool, Void> {
+        request.started()?;
+        if !self.due() {
+            return Ok(false);
+        }
+
+        clocks.start_calibration(request)?;
+        Ok(true)
+    }
+
+    /// Measure the temperature, returning whether a calibration is due
+    fn due(&mut self) -> bool {
+        let temp = self.temp.measure();
+
+        // Temperature change that calls for a calibration, 0.5 °C
+        let delta = I30F2::from_bits(2);
+        let changed = match self.last {
+            Some(last) if last > temp => last - temp >= delta,
+            Some(last) => temp - last >= delta,
+            None => true,
+        };
+
+        if !changed && self.skipped < self.max_skip {
+            self.skipped += 1;
+            return false;
+        }
+
+        self.last = Some(temp);
+        self.skipped = 0;
+
+        true
+    }
+}
+
 impl ClocksExt for CLOCK {
     fn constrain(self) -> Clocks<Internal, Internal, LfOscStopped> {
         Clocks {
@@ -5,6 +5,7 @@ use cortex_m::peripheral::SYST;
 
 use crate::clocks::HFCLK_FREQ;
 use crate::hal::blocking::delay::{DelayMs, DelayUs};
+use crate::time::MicroSeconds;
 
 /// System timer (SysTick) as a delay provider
 pub struct Delay {
@@ -23,6 +24,11 @@ impl Delay {
     pub fn free(self) -> SYST {
         self.syst
     }
+
+    /// Block for `duration`, in any duration unit
+    pub fn delay<D: Into<MicroSeconds>>(&mut self, duration: D) {
+        self.delay_us(duration.into().0);
+    }
 }
 
 impl DelayMs<u32> for Delay {
@@ -0,0 +1,488 @@
+//! HAL interface to the ECB peripheral, AES-128 block encryption
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 24
+//! - nrf52840: Section 6.6
+//!
+//! The ECB encrypts one 16 byte block at a time, reading the key and the
+//! cleartext from and writing the ciphertext to RAM through EasyDMA. It has
+//! the lowest priority of the AES engines: an encryption in progress is
+//! aborted when the CCM or AAR need the engine, which is reported as
+//! `Error::Aborted` by `Ecb::encrypt`, and the block should then be
+//! encrypted again. `BlockEncrypt` does so itself, up to `ENCRYPT_ATTEMPTS`
+//! times, so that the modes of operation are not interrupted mid-message by
+//! a busy radio.
+//!
+//! Block encryption is described by the `BlockEncrypt` trait, which is also
+//! implemented by `SoftAes`, a software model of the peripheral. The modes
+//! of operation built on top, `ctr` and `cbc_mac`, can thus be checked
+//! against test vectors without hardware, the same way as with the ECB.
+use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
+
+use crate::target::ECB;
+use crate::target_constants::{SRAM_LOWER, SRAM_UPPER};
+
+/// Size of an AES block in bytes
+pub const BLOCK_SIZE: usize = 16;
+
+/// An AES block
+pub type Block = [u8; BLOCK_SIZE];
+
+/// An AES-128 key
+pub type Key = [u8; 16];
+
+/// Number of times `Ecb` tries to encrypt a block through `BlockEncrypt`,
+/// before giving up with `Error::Aborted`
+pub const ENCRYPT_ATTEMPTS: usize = 8;
+
+/// AES-128 encryption of single blocks
+pub trait BlockEncrypt {
+    /// Encrypt `block` in place
+    fn encrypt_block(&mut self, block: &mut Block) -> Result<(), Error>;
+}
+
+/// The data structure ECBDATAPTR points to
+#[repr(C)]
+struct EcbData {
+    key: Key,
+    cleartext: Block,
+    ciphertext: Block,
+}
+
+/// Interface to the ECB peripheral
+pub struct Ecb {
+    ecb: ECB,
+    data: EcbData,
+}
+
+impl Ecb {
+    /// Take control of the ECB, encrypting with `key`
+    ///
+    /// The key is given in the byte order of FIPS-197.
+    pub fn new(ecb: ECB, key: &Key) -> Self {
+        ecb.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+
+        Ecb {
+            ecb,
+            data: EcbData {
+                key: *key,
+                cleartext: [0; BLOCK_SIZE],
+                ciphertext: [0; BLOCK_SIZE],
+            },
+        }
+    }
+
+    /// Change the key
+    pub fn set_key(&mut self, key: &Key) {
+        self.data.key = *key;
+    }
+
+    /// Return the raw interface to the ECB, clearing the key from memory
+    pub fn free(mut self) -> ECB {
+        self.data.key = [0; 16];
+        self.data.cleartext = [0; BLOCK_SIZE];
+        self.data.ciphertext = [0; BLOCK_SIZE];
+        compiler_fence(SeqCst);
+
+        self.ecb
+    }
+
+    /// Encrypt a single block, blocking until done
+    pub fn encrypt(&mut self, cleartext: &Block) -> Result<Block, Error> {
+        // The data is copied to `self`, which EasyDMA can only reach when it
+        // is in data RAM, and not in flash as a `static` without `mut` is
+        let p = &self.data as *const EcbData as usize;
+        if p < SRAM_LOWER || p >= SRAM_UPPER {
+            return Err(Error::DMABufferNotInDataMemory);
+        }
+
+        self.data.cleartext = *cleartext;
+
+        // Conservative compiler fence to prevent optimizations that do not
+        // take in to account actions by DMA. The fence has been placed here,
+        // before any DMA action has started
+        compiler_fence(SeqCst);
+
+        self.ecb.events_endecb.reset();
+        self.ecb.events_errorecb.reset();
+        self.ecb.ecbdataptr.write(|w| unsafe { w.bits(p as u32) });
+        self.ecb.tasks_startecb.write(|w| unsafe { w.bits(1) });
+
+        // Encrypting takes about 7 µs
+        loop {
+            if self.ecb.events_endecb.read().bits() != 0 {
+                break;
+            }
+            if self.ecb.events_errorecb.read().bits() != 0 {
+                self.ecb.events_errorecb.reset();
+                return Err(Error::Aborted);
+            }
+        }
+        self.ecb.events_endecb.reset();
+
+        // Conservative compiler fence to prevent optimizations that do not
+        // take in to account actions by DMA. The fence has been placed here,
+        // after all possible DMA actions have completed
+        compiler_fence(SeqCst);
+
+        Ok(self.data.ciphertext)
+    }
+}
+
+impl BlockEncrypt for Ecb {
+    fn encrypt_block(&mut self, block: &mut Block) -> Result<(), Error> {
+        let mut attempts = 1;
+        loop {
+            match self.encrypt(block) {
+                Ok(ciphertext) => {
+                    *block = ciphertext;
+                    return Ok(());
+                }
+                Err(Error::Aborted) if attempts < ENCRYPT_ATTEMPTS => attempts += 1,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Encrypt or decrypt `data` in place in counter (CTR) mode
+///
+/// `counter` is the initial counter block, typically a nonce followed by a
+/// block counter. It is incremented as a 128-bit big endian number for each
+/// block of `data`, and left at the value following the last block used, so
+/// that a message can be processed in several calls as long as all but the
+/// last hold whole blocks. A counter value must never be reused with the
+/// same key.
+///
+/// Should the cipher fail, the blocks before the failing one are processed
+/// and `counter` is left at the failing block: the number of blocks
+/// processed is the difference between `counter` and its initial value, and
+/// the rest of `data` can be processed again from there.
+pub fn ctr<E: BlockEncrypt>(
+    cipher: &mut E,
+    counter: &mut Block,
+    data: &mut [u8],
+) -> Result<(), Error> {
+    for chunk in data.chunks_mut(BLOCK_SIZE) {
+        let mut keystream = *counter;
+        cipher.encrypt_block(&mut keystream)?;
+
+        for (b, k) in chunk.iter_mut().zip(keystream.iter()) {
+            *b ^= k;
+        }
+
+        for byte in counter.iter_mut().rev() {
+            *byte = byte.wrapping_add(1);
+            if *byte != 0 {
+                break;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Compute the CBC-MAC of `data`
+///
+/// The MAC is chained from an all-zero IV, and a partial last block is
+/// padded with zeros. A plain CBC-MAC is only secure for messages of a fixed
+/// length, or with the length encoded in the first block as CCM does.
+pub fn cbc_mac<E: BlockEncrypt>(cipher: &mut E, data: &[u8]) -> Result<Block, Error> {
+    let mut mac = [0; BLOCK_SIZE];
+
+    for chunk in data.chunks(BLOCK_SIZE) {
+        for (m, b) in mac.iter_mut().zip(chunk) {
+            *m ^= b;
+        }
+        cipher.encrypt_block(&mut mac)?;
+    }
+
+    Ok(mac)
+}
+
+/// Software AES-128 encryption, a model of the ECB peripheral
+///
+/// Meant as a reference, and for running code built on `BlockEncrypt` on
+/// the host. The table lookups it uses make its timing depend on the data
+/// on CPUs with a cache.
+pub struct SoftAes {
+    round_keys: [Block; ROUNDS + 1],
+}
+
+/// Number of rounds of AES-128
+const ROUNDS: usize = 10;
+
+impl SoftAes {
+    /// Create the model, encrypting with `key`
+    pub fn new(key: &Key) -> Self {
+        let mut round_keys = [[0; BLOCK_SIZE]; ROUNDS + 1];
+        round_keys[0] = *key;
+
+        for round in 1..=ROUNDS {
+            let prev = round_keys[round - 1];
+            let mut word = [
+                SBOX[prev[13] as usize] ^ RCON[round - 1],
+                SBOX[prev[14] as usize],
+                SBOX[prev[15] as usize],
+                SBOX[prev[12] as usize],
+            ];
+
+            let next = round_keys[round].iter_mut().zip(prev.iter());
+            for (i, (n, p)) in next.enumerate() {
+                word[i % 4] ^= p;
+                *n = word[i % 4];
+            }
+        }
+
+        SoftAes { round_keys }
+    }
+
+    /// Encrypt a single block
+    pub fn encrypt(&self, cleartext: &Block) -> Block {
+        let mut state = *cleartext;
+
+        add_round_key(&mut state, &self.round_keys[0]);
+        for round_key in &self.round_keys[1..ROUNDS] {
+            sub_bytes(&mut state);
+            shift_rows(&mut state);
+            mix_columns(&mut state);
+            add_round_key(&mut state, round_key);
+        }
+        sub_bytes(&mut state);
+        shift_rows(&mut state);
+        add_round_key(&mut state, &self.round_keys[ROUNDS]);
+
+        state
+    }
+}
+
+impl BlockEncrypt for SoftAes {
+    fn encrypt_block(&mut self, block: &mut Block) -> Result<(), Error> {
+        *block = self.encrypt(block);
+        Ok(())
+    }
+}
+
+// The state is stored column by column, byte `4 * c + r` being at row `r`
+// of column `c`, which is the byte order of the blocks
+
+fn add_round_key(state: &mut Block, key: &Block) {
+    for (s, k) in state.iter_mut().zip(key.iter()) {
+        *s ^= k;
+    }
+}
+
+fn sub_bytes(state: &mut Block) {
+    for s in state.iter_mut() {
+        *s = SBOX[*s as usize];
+    }
+}
+
+/// Rotate row `r` left by `r` bytes
+fn shift_rows(state: &mut Block) {
+    let old = *state;
+    for c in 0..4 {
+        for r in 1..4 {
+            state[4 * c + r] = old[4 * ((c + r) % 4) + r];
+        }
+    }
+}
+
+fn mix_columns(state: &mut Block) {
+    for column in state.chunks_mut(4) {
+        let a = [column[0], column[1], column[2], column[3]];
+        let all = a[0] ^ a[1] ^ a[2] ^ a[3];
+        for (r, s) in column.iter_mut().enumerate() {
+            *s ^= all ^ xtime(a[r] ^ a[(r + 1) % 4]);
+        }
+    }
+}
+
+/// Multiplication by x in GF(2^8)
+fn xtime(b: u8) -> u8 {
+    (b << 1) ^ if b & 0x80 != 0 { 0x1B } else { 0 }
+}
+
+/// Round constants of the key schedule
+const RCON: [u8; ROUNDS] = [0x01, 0x02, 0x04, 0x08, 0x10, 0x20, 0x40, 0x80, 0x1B, 0x36];
+
+/// The AES S-box
+#[rustfmt::skip]
+const SBOX: [u8; 256] = [
+    0x63, 0x7C, 0x77, 0x7B, 0xF2, 0x6B, 0x6F, 0xC5, 0x30, 0x01, 0x67, 0x2B, 0xFE, 0xD7, 0xAB, 0x76,
+    0xCA, 0x82, 0xC9, 0x7D, 0xFA, 0x59, 0x47, 0xF0, 0xAD, 0xD4, 0xA2, 0xAF, 0x9C, 0xA4, 0x72, 0xC0,
+    0xB7, 0xFD, 0x93, 0x26, 0x36, 0x3F, 0xF7, 0xCC, 0x34, 0xA5, 0xE5, 0xF1, 0x71, 0xD8, 0x31, 0x15,
+    0x04, 0xC7, 0x23, 0xC3, 0x18, 0x96, 0x05, 0x9A, 0x07, 0x12, 0x80, 0xE2, 0xEB, 0x27, 0xB2, 0x75,
+    0x09, 0x83, 0x2C, 0x1A, 0x1B, 0x6E, 0x5A, 0xA0, 0x52, 0x3B, 0xD6, 0xB3, 0x29, 0xE3, 0x2F, 0x84,
+    0x53, 0xD1, 0x00, 0xED, 0x20, 0xFC, 0xB1, 0x5B, 0x6A, 0xCB, 0xBE, 0x39, 0x4A, 0x4C, 0x58, 0xCF,
+    0xD0, 0xEF, 0xAA, 0xFB, 0x43, 0x4D, 0x33, 0x85, 0x45, 0xF9, 0x02, 0x7F, 0x50, 0x3C, 0x9F, 0xA8,
+    0x51, 0xA3, 0x40, 0x8F, 0x92, 0x9D, 0x38, 0xF5, 0xBC, 0xB6, 0xDA, 0x21, 0x10, 0xFF, 0xF3, 0xD2,
+    0xCD, 0x0C, 0x13, 0xEC, 0x5F, 0x97, 0x44, 0x17, 0xC4, 0xA7, 0x7E, 0x3D, 0x64, 0x5D, 0x19, 0x73,
+    0x60, 0x81, 0x4F, 0xDC, 0x22, 0x2A, 0x90, 0x88, 0x46, 0xEE, 0xB8, 0x14, 0xDE, 0x5E, 0x0B, 0xDB,
+    0xE0, 0x32, 0x3A, 0x0A, 0x49, 0x06, 0x24, 0x5C, 0xC2, 0xD3, 0xAC, 0x62, 0x91, 0x95, 0xE4, 0x79,
+    0xE7, 0xC8, 0x37, 0x6D, 0x8D, 0xD5, 0x4E, 0xA9, 0x6C, 0x56, 0xF4, 0xEA, 0x65, 0x7A, 0xAE, 0x08,
+    0xBA, 0x78, 0x25, 0x2E, 0x1C, 0xA6, 0xB4, 0xC6, 0xE8, 0xDD, 0x74, 0x1F, 0x4B, 0xBD, 0x8B, 0x8A,
+    0x70, 0x3E, 0xB5, 0x66, 0x48, 0x03, 0xF6, 0x0E, 0x61, 0x35, 0x57, 0xB9, 0x86, 0xC1, 0x1D, 0x9E,
+    0xE1, 0xF8, 0x98, 0x11, 0x69, 0xD9, 0x8E, 0x94, 0x9B, 0x1E, 0x87, 0xE9, 0xCE, 0x55, 0x28, 0xDF,
+    0x8C, 0xA1, 0x89, 0x0D, 0xBF, 0xE6, 0x42, 0x68, 0x41, 0x99, 0x2D, 0x0F, 0xB0, 0x54, 0xBB, 0x16,
+];
+
+/// Error types associated with AES encryption
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The encryption was aborted by a higher priority AES engine
+    Aborted,
+    /// The data structure handed to EasyDMA is not in data RAM
+    DMABufferNotInDataMemory,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// SP 800-38A, Appendix F
+    const SP800_38A_KEY: Key = [
+        0x2B, 0x7E, 0x15, 0x16, 0x28, 0xAE, 0xD2, 0xA6, 0xAB, 0xF7, 0x15, 0x88, 0x09, 0xCF, 0x4F,
+        0x3C,
+    ];
+    const SP800_38A_PLAINTEXT: [u8; 64] = [
+        0x6B, 0xC1, 0xBE, 0xE2, 0x2E, 0x40, 0x9F, 0x96, 0xE9, 0x3D, 0x7E, 0x11, 0x73, 0x93, 0x17,
+        0x2A, 0xAE, 0x2D, 0x8A, 0x57, 0x1E, 0x03, 0xAC, 0x9C, 0x9E, 0xB7, 0x6F, 0xAC, 0x45, 0xAF,
+        0x8E, 0x51, 0x30, 0xC8, 0x1C, 0x46, 0xA3, 0x5C, 0xE4, 0x11, 0xE5, 0xFB, 0xC1, 0x19, 0x1A,
+        0x0A, 0x52, 0xEF, 0xF6, 0x9F, 0x24, 0x45, 0xDF, 0x4F, 0x9B, 0x17, 0xAD, 0x2B, 0x41, 0x7B,
+        0xE6, 0x6C, 0x37, 0x10,
+    ];
+
+    #[test]
+    fn fips_197_block() {
+        // FIPS-197, Appendix C.1
+        let aes = SoftAes::new(&[
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+            0x0E, 0x0F,
+        ]);
+        let ciphertext = aes.encrypt(&[
+            0x00, 0x11, 0x22, 0x33, 0x44, 0x55, 0x66, 0x77, 0x88, 0x99, 0xAA, 0xBB, 0xCC, 0xDD,
+            0xEE, 0xFF,
+        ]);
+
+        assert_eq!(
+            ciphertext,
+            [
+                0x69, 0xC4, 0xE0, 0xD8, 0x6A, 0x7B, 0x04, 0x30, 0xD8, 0xCD, 0xB7, 0x80, 0x70, 0xB4,
+                0xC5, 0x5A
+            ]
+        );
+    }
+
+    #[test]
+    fn sp800_38a_ctr() {
+        // SP 800-38A, Appendix F.5.1
+        let ciphertext = [
+            0x87, 0x4D, 0x61, 0x91, 0xB6, 0x20, 0xE3, 0x26, 0x1B, 0xEF, 0x68, 0x64, 0x99, 0x0D,
+            0xB6, 0xCE, 0x98, 0x06, 0xF6, 0x6B, 0x79, 0x70, 0xFD, 0xFF, 0x86, 0x17, 0x18, 0x7B,
+            0xB9, 0xFF, 0xFD, 0xFF, 0x5A, 0xE4, 0xDF, 0x3E, 0xDB, 0xD5, 0xD3, 0x5E, 0x5B, 0x4F,
+            0x09, 0x02, 0x0D, 0xB0, 0x3E, 0xAB, 0x1E, 0x03, 0x1D, 0xDA, 0x2F, 0xBE, 0x03, 0xD1,
+            0x79, 0x21, 0x70, 0xA0, 0xF3, 0x00, 0x9C, 0xEE,
+        ];
+        let initial = [
+            0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD,
+            0xFE, 0xFF,
+        ];
+        let mut aes = SoftAes::new(&SP800_38A_KEY);
+
+        let mut counter = initial;
+        let mut data = SP800_38A_PLAINTEXT;
+        ctr(&mut aes, &mut counter, &mut data).unwrap();
+        assert!(data[..] == ciphertext[..]);
+        assert_eq!(
+            counter,
+            [
+                0xF0, 0xF1, 0xF2, 0xF3, 0xF4, 0xF5, 0xF6, 0xF7, 0xF8, 0xF9, 0xFA, 0xFB, 0xFC, 0xFD,
+                0xFF, 0x03
+            ]
+        );
+
+        // In several calls, the last one with a partial block
+        let mut counter = initial;
+        let (head, tail) = data.split_at_mut(2 * BLOCK_SIZE);
+        ctr(&mut aes, &mut counter, head).unwrap();
+        ctr(&mut aes, &mut counter, tail).unwrap();
+        assert!(data[..] == SP800_38A_PLAINTEXT[..]);
+    }
+
+    /// Fails once, on the block with index `fail_at`
+    struct Flaky {
+        aes: SoftAes,
+        fail_at: usize,
+        blocks: usize,
+    }
+
+    impl BlockEncrypt for Flaky {
+        fn encrypt_block(&mut self, block: &mut Block) -> Result<(), Error> {
+            self.blocks += 1;
+            if self.blocks == self.fail_at + 1 {
+                return Err(Error::Aborted);
+            }
+            self.aes.encrypt_block(block)
+        }
+    }
+
+    #[test]
+    fn ctr_resume() {
+        let initial = [0; BLOCK_SIZE];
+        let mut cipher = Flaky {
+            aes: SoftAes::new(&SP800_38A_KEY),
+            fail_at: 2,
+            blocks: 0,
+        };
+
+        let mut counter = initial;
+        let mut data = SP800_38A_PLAINTEXT;
+        assert_eq!(
+            ctr(&mut cipher, &mut counter, &mut data),
+            Err(Error::Aborted)
+        );
+        assert_eq!(counter[BLOCK_SIZE - 1], 2);
+        assert!(data[2 * BLOCK_SIZE..] == SP800_38A_PLAINTEXT[2 * BLOCK_SIZE..]);
+
+        ctr(&mut cipher, &mut counter, &mut data[2 * BLOCK_SIZE..]).unwrap();
+
+        let mut counter = initial;
+        let mut expected = SP800_38A_PLAINTEXT;
+        ctr(&mut cipher.aes, &mut counter, &mut expected).unwrap();
+        assert!(data[..] == expected[..]);
+    }
+
+    #[test]
+    fn sp800_38a_cbc_mac() {
+        // SP 800-38A, Appendix F.2.1: the CBC-MAC is the last ciphertext
+        // block, once the IV is folded into the first block
+        let iv = [
+            0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D,
+            0x0E, 0x0F,
+        ];
+        let mut data = SP800_38A_PLAINTEXT;
+        for (d, i) in data.iter_mut().zip(iv.iter()) {
+            *d ^= i;
+        }
+        let mut aes = SoftAes::new(&SP800_38A_KEY);
+
+        let mac = cbc_mac(&mut aes, &data).unwrap();
+        assert_eq!(
+            mac,
+            [
+                0x3F, 0xF1, 0xCA, 0xA1, 0x68, 0x1F, 0xAC, 0x09, 0x12, 0x0E, 0xCA, 0x30, 0x75, 0x86,
+                0xE1, 0xA7
+            ]
+        );
+
+        // A partial last block is padded with zeros
+        let mut padded = [0; 2 * BLOCK_SIZE];
+        padded[..20].copy_from_slice(&data[..20]);
+        assert_eq!(
+            cbc_mac(&mut aes, &data[..20]).unwrap(),
+            cbc_mac(&mut aes, &padded).unwrap()
+        );
+    }
+}
@@ -0,0 +1,538 @@
+//! Packet framing over a byte stream, using COBS or SLIP
+//!
+//! The split UARTE hands out received data in chunks that bear no relation
+//! to packet boundaries. A `Decoder` accumulates the chunks into frames,
+//! delimited either by a zero byte (COBS, Consistent Overhead Byte Stuffing)
+//! or by the SLIP `END` byte (RFC 1055). Frames to transmit are produced by
+//! `encode`.
+//!
+//! A frame that is malformed or does not fit the decoder's buffer is
+//! reported as an error once its delimiter is seen, after which decoding
+//! resumes with the next frame. The errors convert into `uarte::RXError`.
+//!
+//! This module only depends on `core`, so host tools talking to the board
+//! can use the very same codec by including the file:
+//!
+//! ```ignore
+//! #[path = "../nrf52-hal/nrf52-hal-common/src/framing.rs"]
+//! mod framing;
+//! ```
+
+/// SLIP frame delimiter
+const SLIP_END: u8 = 0xC0;
+/// SLIP escape byte
+const SLIP_ESC: u8 = 0xDB;
+/// Escaped `SLIP_END`
+const SLIP_ESC_END: u8 = 0xDC;
+/// Escaped `SLIP_ESC`
+const SLIP_ESC_ESC: u8 = 0xDD;
+
+/// COBS frame delimiter
+const COBS_END: u8 = 0x00;
+
+/// Framing method
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    /// Consistent Overhead Byte Stuffing, frames end with a zero byte
+    Cobs,
+    /// Serial Line Internet Protocol, frames end with `0xC0`
+    Slip,
+}
+
+impl Encoding {
+    /// Largest encoded size of a frame of `len` bytes, delimiters included
+    pub fn max_encoded_len(self, len: usize) -> usize {
+        match self {
+            // One code byte per started block of 254 bytes, and the delimiter
+            Encoding::Cobs => len + len / 254 + 2,
+            // Every byte may need escaping, and delimiters on both ends
+            Encoding::Slip => 2 * len + 2,
+        }
+    }
+}
+
+/// Encode `frame` into `buf`, returning the number of bytes used
+///
+/// The encoded frame includes its delimiter. SLIP frames also start with
+/// `END`, which flushes any line noise received before the frame.
+pub fn encode(encoding: Encoding, frame: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+    if buf.len() < encoding.max_encoded_len(frame.len()) {
+        return Err(Error::Overflow);
+    }
+
+    match encoding {
+        Encoding::Cobs => Ok(encode_cobs(frame, buf)),
+        Encoding::Slip => Ok(encode_slip(frame, buf)),
+    }
+}
+
+fn encode_cobs(frame: &[u8], buf: &mut [u8]) -> usize {
+    let mut code_index = 0;
+    let mut code = 1;
+    let mut n = 1;
+
+    for &byte in frame {
+        if byte == 0 {
+            buf[code_index] = code;
+            code_index = n;
+            n += 1;
+            code = 1;
+        } else {
+            buf[n] = byte;
+            n += 1;
+            code += 1;
+            if code == 0xFF {
+                buf[code_index] = code;
+                code_index = n;
+                n += 1;
+                code = 1;
+            }
+        }
+    }
+
+    buf[code_index] = code;
+    buf[n] = COBS_END;
+    n + 1
+}
+
+fn encode_slip(frame: &[u8], buf: &mut [u8]) -> usize {
+    let mut n = 0;
+    let mut put = |byte| {
+        buf[n] = byte;
+        n += 1;
+    };
+
+    put(SLIP_END);
+    for &byte in frame {
+        match byte {
+            SLIP_END => {
+                put(SLIP_ESC);
+                put(SLIP_ESC_END);
+            }
+            SLIP_ESC => {
+                put(SLIP_ESC);
+                put(SLIP_ESC_ESC);
+            }
+            _ => put(byte),
+        }
+    }
+    put(SLIP_END);
+
+    n
+}
+
+/// Streaming frame decoder
+///
+/// Decoded frames are accumulated in a buffer supplied by the user, which
+/// bounds the size of the frames that can be received.
+pub struct Decoder<'a> {
+    encoding: Encoding,
+    buf: &'a mut [u8],
+    len: usize,
+    state: State,
+    error: Option<Error>,
+}
+
+#[derive(Clone, Copy)]
+enum State {
+    /// No byte of the frame seen yet
+    Idle,
+    /// COBS: `remaining` bytes left in the block started by `code`
+    Cobs { code: u8, remaining: u8 },
+    /// SLIP: within a frame, `escape` after an `ESC` byte
+    Slip { escape: bool },
+}
+
+impl<'a> Decoder<'a> {
+    /// Create a decoder, accumulating frames in `buf`
+    pub fn new(encoding: Encoding, buf: &'a mut [u8]) -> Self {
+        Decoder {
+            encoding,
+            buf,
+            len: 0,
+            state: State::Idle,
+            error: None,
+        }
+    }
+
+    /// Drop the partially received frame
+    pub fn reset(&mut self) {
+        self.len = 0;
+        self.state = State::Idle;
+        self.error = None;
+    }
+
+    /// Return the frame buffer
+    pub fn free(self) -> &'a mut [u8] {
+        self.buf
+    }
+
+    /// Decode the received `data`
+    ///
+    /// `on_frame` is called for every complete frame, with the decoded frame
+    /// or the reason it was dropped. Empty frames are skipped. A partial frame
+    /// at the end of `data` is kept, to be completed by later calls.
+    pub fn feed<F>(&mut self, data: &[u8], mut on_frame: F)
+    where
+        F: FnMut(Result<&[u8], Error>),
+    {
+        for &byte in data {
+            if self.push(byte) {
+                match self.error.take() {
+                    Some(e) => on_frame(Err(e)),
+                    None if self.len > 0 => on_frame(Ok(&self.buf[..self.len])),
+                    None => (),
+                }
+                self.reset();
+            }
+        }
+    }
+
+    /// Decode a single byte, returning `true` at the end of a frame
+    fn push(&mut self, byte: u8) -> bool {
+        match self.encoding {
+            Encoding::Cobs => self.push_cobs(byte),
+            Encoding::Slip => self.push_slip(byte),
+        }
+    }
+
+    fn push_cobs(&mut self, byte: u8) -> bool {
+        if byte == COBS_END {
+            if let State::Cobs { remaining, .. } = self.state {
+                if remaining != 0 {
+                    self.fail(Error::Framing);
+                }
+            }
+            return true;
+        }
+
+        self.state = match self.state {
+            State::Cobs { code, remaining: 0 } => {
+                // A block shorter than the maximum implies a zero byte
+                if code != 0xFF {
+                    self.store(0);
+                }
+                State::Cobs {
+                    code: byte,
+                    remaining: byte - 1,
+                }
+            }
+            State::Cobs { code, remaining } => {
+                self.store(byte);
+                State::Cobs {
+                    code,
+                    remaining: remaining - 1,
+                }
+            }
+            _ => State::Cobs {
+                code: byte,
+                remaining: byte - 1,
+            },
+        };
+
+        false
+    }
+
+    fn push_slip(&mut self, byte: u8) -> bool {
+        let escape = match self.state {
+            State::Slip { escape } => escape,
+            _ => false,
+        };
+
+        if byte == SLIP_END {
+            if escape {
+                self.fail(Error::Framing);
+            }
+            return true;
+        }
+
+        self.state = if escape {
+            match byte {
+                SLIP_ESC_END => self.store(SLIP_END),
+                SLIP_ESC_ESC => self.store(SLIP_ESC),
+                _ => self.fail(Error::Framing),
+            }
+            State::Slip { escape: false }
+        } else if byte == SLIP_ESC {
+            State::Slip { escape: true }
+        } else {
+            self.store(byte);
+            State::Slip { escape: false }
+        };
+
+        false
+    }
+
+    fn store(&mut self, byte: u8) {
+        if self.error.is_some() {
+            return;
+        }
+        if self.len == self.buf.len() {
+            self.fail(Error::Overflow);
+            return;
+        }
+
+        self.buf[self.len] = byte;
+        self.len += 1;
+    }
+
+    /// Drop the rest of the frame, keeping the first error
+    fn fail(&mut self, e: Error) {
+        if self.error.is_none() {
+            self.error = Some(e);
+        }
+    }
+}
+
+/// Error types associated with framing
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The frame is malformed
+    Framing,
+    /// The frame does not fit the buffer
+    Overflow,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes 1 to 255, repeated, so without any zero
+    fn nonzero(len: usize) -> [u8; 600] {
+        let mut frame = [0; 600];
+        for (i, b) in frame[..len].iter_mut().enumerate() {
+            *b = (i % 255 + 1) as u8;
+        }
+        frame
+    }
+
+    /// Encode `frame`, returning the encoded bytes and their number
+    fn encoded(encoding: Encoding, frame: &[u8]) -> ([u8; 1400], usize) {
+        let mut buf = [0; 1400];
+        let n = encode(encoding, frame, &mut buf).unwrap();
+        assert!(n <= encoding.max_encoded_len(frame.len()));
+        (buf, n)
+    }
+
+    /// Encode `frame` and decode it from chunks of `chunk` bytes
+    fn roundtrip(encoding: Encoding, frame: &[u8], chunk: usize) {
+        let (data, n) = encoded(encoding, frame);
+        let end = match encoding {
+            Encoding::Cobs => COBS_END,
+            Encoding::Slip => SLIP_END,
+        };
+        assert_eq!(data[n - 1], end);
+        assert!(!data[1..n - 1].contains(&end));
+
+        let mut buf = [0; 600];
+        let mut decoder = Decoder::new(encoding, &mut buf);
+        let mut frames = 0;
+        for chunk in data[..n].chunks(chunk) {
+            decoder.feed(chunk, |result| {
+                assert_eq!(result, Ok(frame));
+                frames += 1;
+            });
+        }
+
+        // Empty frames are skipped
+        assert_eq!(frames, if frame.is_empty() { 0 } else { 1 });
+    }
+
+    /// Decode `data`, which must hold a single frame, into `buf`
+    fn decode(encoding: Encoding, data: &[u8], buf: &mut [u8]) -> Result<usize, Error> {
+        let mut decoder = Decoder::new(encoding, buf);
+        let mut decoded = None;
+        decoder.feed(data, |result| {
+            assert_eq!(decoded, None);
+            decoded = Some(result.map(|frame| frame.len()));
+        });
+        decoded.unwrap()
+    }
+
+    fn roundtrip_all(frame: &[u8]) {
+        for &encoding in &[Encoding::Cobs, Encoding::Slip] {
+            for &chunk in &[1, 3, 1400] {
+                roundtrip(encoding, frame, chunk);
+            }
+        }
+    }
+
+    #[test]
+    fn empty_frames() {
+        roundtrip_all(&[]);
+        assert_eq!(encoded(Encoding::Cobs, &[]).1, 2);
+        assert_eq!(encoded(Encoding::Slip, &[]).1, 2);
+    }
+
+    #[test]
+    fn cobs_runs() {
+        for &len in &[1, 253, 254, 255, 508, 509, 600] {
+            roundtrip_all(&nonzero(len)[..len]);
+        }
+
+        // 254 bytes fill a block, which implies no zero byte
+        let (data, n) = encoded(Encoding::Cobs, &nonzero(254)[..254]);
+        assert_eq!(n, 257);
+        assert_eq!(data[0], 0xFF);
+        assert_eq!(&data[255..257], &[0x01, 0x00]);
+
+        let (data, n) = encoded(Encoding::Cobs, &nonzero(255)[..255]);
+        assert_eq!(n, 258);
+        assert_eq!(data[0], 0xFF);
+        assert_eq!(&data[255..258], &[0x02, 0xFF, 0x00]);
+    }
+
+    #[test]
+    fn zero_bytes() {
+        roundtrip_all(&[0]);
+        roundtrip_all(&[0, 0]);
+        roundtrip_all(&[1, 0, 2, 0]);
+        roundtrip_all(&[0; 300]);
+
+        let mut frame = nonzero(300);
+        frame[253] = 0;
+        frame[254] = 0;
+        roundtrip_all(&frame[..300]);
+
+        assert_eq!(
+            &encoded(Encoding::Cobs, &[0x11, 0x00, 0x22]).0[..5],
+            &[0x02, 0x11, 0x02, 0x22, 0x00]
+        );
+    }
+
+    #[test]
+    fn slip_escaping() {
+        let frame = [SLIP_END, 1, SLIP_ESC, SLIP_ESC_END, SLIP_ESC_ESC];
+        roundtrip_all(&frame);
+
+        let (data, n) = encoded(Encoding::Slip, &frame);
+        assert_eq!(
+            &data[..n],
+            &[
+                SLIP_END,
+                SLIP_ESC,
+                SLIP_ESC_END,
+                1,
+                SLIP_ESC,
+                SLIP_ESC_ESC,
+                SLIP_ESC_END,
+                SLIP_ESC_ESC,
+                SLIP_END
+            ]
+        );
+    }
+
+    #[test]
+    fn several_frames() {
+        for &encoding in &[Encoding::Cobs, Encoding::Slip] {
+            let mut data = [0; 64];
+            let mut n = 0;
+            for frame in &[&b"one"[..], &b""[..], &b"\x00two\xC0"[..]] {
+                n += encode(encoding, frame, &mut data[n..]).unwrap();
+            }
+
+            let mut buf = [0; 16];
+            let mut decoder = Decoder::new(encoding, &mut buf);
+            let mut frames = 0;
+            decoder.feed(&data[..n], |result| {
+                let expected: &[u8] = if frames == 0 { b"one" } else { b"\x00two\xC0" };
+                assert_eq!(result, Ok(expected));
+                frames += 1;
+            });
+            assert_eq!(frames, 2);
+        }
+    }
+
+    #[test]
+    fn encode_overflow() {
+        for &encoding in &[Encoding::Cobs, Encoding::Slip] {
+            let frame = [0xC0; 8];
+            let mut buf = [0; 32];
+            let len = encoding.max_encoded_len(frame.len());
+            assert_eq!(
+                encode(encoding, &frame, &mut buf[..len - 1]),
+                Err(Error::Overflow)
+            );
+            assert!(encode(encoding, &frame, &mut buf[..len]).is_ok());
+        }
+    }
+
+    #[test]
+    fn decode_overflow() {
+        for &encoding in &[Encoding::Cobs, Encoding::Slip] {
+            let mut data = [0; 64];
+            let mut n = encode(encoding, b"too long", &mut data).unwrap();
+            n += encode(encoding, b"fits", &mut data[n..]).unwrap();
+
+            // The frame that does not fit is dropped, the next one is decoded
+            let mut buf = [0; 4];
+            let mut decoder = Decoder::new(encoding, &mut buf);
+            let mut frames = 0;
+            decoder.feed(&data[..n], |result| {
+                match frames {
+                    0 => assert_eq!(result, Err(Error::Overflow)),
+                    _ => assert_eq!(result, Ok(&b"fits"[..])),
+                }
+                frames += 1;
+            });
+            assert_eq!(frames, 2);
+        }
+    }
+
+    #[test]
+    fn cobs_framing_error() {
+        let mut buf = [0; 16];
+
+        // The block announces three bytes, the delimiter comes after two
+        assert_eq!(
+            decode(Encoding::Cobs, &[0x04, 0x11, 0x22, 0x00], &mut buf),
+            Err(Error::Framing)
+        );
+        assert_eq!(
+            decode(Encoding::Cobs, &[0x03, 0x11, 0x22, 0x00], &mut buf),
+            Ok(2)
+        );
+
+        // Decoding resumes after the malformed frame
+        let mut decoder = Decoder::new(Encoding::Cobs, &mut buf);
+        let mut results = [None; 2];
+        let mut i = 0;
+        decoder.feed(&[0x05, 0x11, 0x00, 0x02, 0x33, 0x00], |result| {
+            results[i] = Some(result.map(|frame| frame[0]));
+            i += 1;
+        });
+        assert_eq!(results, [Some(Err(Error::Framing)), Some(Ok(0x33))]);
+    }
+
+    #[test]
+    fn slip_framing_error() {
+        let mut buf = [0; 16];
+
+        // An escape must be followed by `ESC_END` or `ESC_ESC`
+        assert_eq!(
+            decode(
+                Encoding::Slip,
+                &[SLIP_END, SLIP_ESC, 0x11, SLIP_END],
+                &mut buf
+            ),
+            Err(Error::Framing)
+        );
+        assert_eq!(
+            decode(
+                Encoding::Slip,
+                &[SLIP_END, 0x11, SLIP_ESC, SLIP_END],
+                &mut buf
+            ),
+            Err(Error::Framing)
+        );
+        assert_eq!(
+            decode(
+                Encoding::Slip,
+                &[SLIP_ESC, SLIP_ESC_ESC, SLIP_END],
+                &mut buf
+            ),
+            Ok(1)
+        );
+        assert_eq!(buf[0], SLIP_ESC);
+    }
+}
@@ -0,0 +1,446 @@
+//! HAL interface to the GPIOTE peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 20
+//! - nrf52840: Section 6.10
+//!
+//! The GPIOTE peripheral is split into eight independent channels and a PORT
+//! event. A channel is turned into an `EventChannel` by handing it an input
+//! pin, or into a `TaskChannel` by handing it an output pin. The pin is held
+//! by the channel until it is released again with `free`.
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use crate::gpio::{Input, Level, Output, Pin};
+use crate::target::{gpiote, p0, GPIOTE, P0};
+
+#[cfg(feature = "52840")]
+use crate::target::P1;
+
+/// Number of GPIOTE channels
+pub const NUM_CHANNELS: usize = 8;
+
+/// Bit in INTENSET/INTENCLR for the PORT event
+const PORT_INT_BIT: u32 = 1 << 31;
+
+/// Extension trait to split the GPIOTE peripheral in independent channels
+pub trait GpioteExt: Deref<Target = gpiote::RegisterBlock> + Sized {
+    /// Splits the GPIOTE block into independent channels
+    fn split(self) -> Parts;
+}
+
+impl GpioteExt for GPIOTE {
+    fn split(self) -> Parts {
+        // Start from a known state, the channels are handed out unconfigured
+        self.intenclr.write(|w| unsafe { w.bits(0xFFFF_FFFF) });
+        for i in 0..NUM_CHANNELS {
+            self.config[i].write(|w| w.mode().disabled());
+            self.events_in[i].reset();
+        }
+        self.events_port.reset();
+
+        Parts {
+            channel0: Channel::new(),
+            channel1: Channel::new(),
+            channel2: Channel::new(),
+            channel3: Channel::new(),
+            channel4: Channel::new(),
+            channel5: Channel::new(),
+            channel6: Channel::new(),
+            channel7: Channel::new(),
+            port: Port { _private: () },
+            handler: Handler { _private: () },
+        }
+    }
+}
+
+/// GPIOTE parts
+pub struct Parts {
+    pub channel0: Channel<C0>,
+    pub channel1: Channel<C1>,
+    pub channel2: Channel<C2>,
+    pub channel3: Channel<C3>,
+    pub channel4: Channel<C4>,
+    pub channel5: Channel<C5>,
+    pub channel6: Channel<C6>,
+    pub channel7: Channel<C7>,
+    /// The PORT event, driven by the DETECT signal of the GPIO port(s)
+    pub port: Port,
+    /// Interrupt handler, to be owned by the `GPIOTE` interrupt
+    pub handler: Handler,
+}
+
+/// Channel number of a GPIOTE channel (type state)
+pub trait ChannelNum {
+    const INDEX: usize;
+}
+
+macro_rules! channels {
+    ($($C:ident: $i:expr,)+) => {
+        $(
+            /// GPIOTE channel (type state)
+            pub struct $C;
+
+            impl ChannelNum for $C {
+                const INDEX: usize = $i;
+            }
+        )+
+    }
+}
+
+channels!(
+    C0: 0,
+    C1: 1,
+    C2: 2,
+    C3: 3,
+    C4: 4,
+    C5: 5,
+    C6: 6,
+    C7: 7,
+);
+
+/// Edge (or edges) of the input signal generating an IN event
+pub enum Edge {
+    /// Low to high transition
+    Rising,
+    /// High to low transition
+    Falling,
+    /// Any transition
+    Toggle,
+}
+
+/// Action performed on the output pin by the OUT task
+pub enum TaskOut {
+    /// Set the pin high
+    Set,
+    /// Set the pin low
+    Clear,
+    /// Toggle the pin
+    Toggle,
+}
+
+/// Pin level that makes the DETECT signal (and thus the PORT event) fire
+pub enum Sense {
+    /// Pin does not take part in DETECT
+    Disabled,
+    /// Sense for a high level
+    High,
+    /// Sense for a low level
+    Low,
+}
+
+fn regs() -> &'static gpiote::RegisterBlock {
+    // NOTE(unsafe) each channel type only touches its own registers, and the
+    // shared INTENSET/INTENCLR registers are write-one-to-set/clear
+    unsafe { &*GPIOTE::ptr() }
+}
+
+/// An unconfigured GPIOTE channel
+pub struct Channel<C> {
+    _channel: PhantomData<C>,
+}
+
+impl<C> Channel<C>
+where
+    C: ChannelNum,
+{
+    fn new() -> Self {
+        Channel {
+            _channel: PhantomData,
+        }
+    }
+
+    /// Use the channel to generate IN events on the given `edge` of `pin`
+    pub fn input_pin<MODE>(
+        self,
+        pin: Pin<Input<MODE>>,
+        edge: Edge,
+    ) -> EventChannel<C, MODE> {
+        let gpiote = regs();
+
+        gpiote.config[C::INDEX].write(|w| {
+            let w = match edge {
+                Edge::Rising => w.polarity().lo_to_hi(),
+                Edge::Falling => w.polarity().hi_to_lo(),
+                Edge::Toggle => w.polarity().toggle(),
+            };
+            let w = unsafe { w.psel().bits(pin.pin) };
+            #[cfg(feature = "52840")]
+            let w = w.port().bit(pin.port);
+            w.mode().event()
+        });
+
+        // The configuration change may itself generate an event
+        gpiote.events_in[C::INDEX].reset();
+
+        EventChannel {
+            pin,
+            _channel: PhantomData,
+        }
+    }
+
+    /// Use the channel to drive `pin` from the OUT (and SET/CLR) tasks
+    ///
+    /// While the channel is configured the pin is controlled by GPIOTE, and
+    /// the output level in the GPIO OUT register is ignored.
+    pub fn output_pin<MODE>(
+        self,
+        pin: Pin<Output<MODE>>,
+        task: TaskOut,
+        initial_output: Level,
+    ) -> TaskChannel<C, MODE> {
+        regs().config[C::INDEX].write(|w| {
+            let w = match task {
+                TaskOut::Set => w.polarity().lo_to_hi(),
+                TaskOut::Clear => w.polarity().hi_to_lo(),
+                TaskOut::Toggle => w.polarity().toggle(),
+            };
+            let w = match initial_output {
+                Level::Low => w.outinit().low(),
+                Level::High => w.outinit().high(),
+            };
+            let w = unsafe { w.psel().bits(pin.pin) };
+            #[cfg(feature = "52840")]
+            let w = w.port().bit(pin.port);
+            w.mode().task()
+        });
+
+        TaskChannel {
+            pin,
+            _channel: PhantomData,
+        }
+    }
+}
+
+/// A GPIOTE channel generating IN events from an input pin
+pub struct EventChannel<C, MODE> {
+    pin: Pin<Input<MODE>>,
+    _channel: PhantomData<C>,
+}
+
+impl<C, MODE> EventChannel<C, MODE>
+where
+    C: ChannelNum,
+{
+    /// Enable the GPIOTE interrupt for the IN event of this channel
+    pub fn enable_interrupt(&mut self) {
+        regs()
+            .intenset
+            .write(|w| unsafe { w.bits(1 << C::INDEX) });
+    }
+
+    /// Disable the GPIOTE interrupt for the IN event of this channel
+    pub fn disable_interrupt(&mut self) {
+        regs()
+            .intenclr
+            .write(|w| unsafe { w.bits(1 << C::INDEX) });
+    }
+
+    /// Has the IN event been generated since it was last cleared?
+    pub fn is_triggered(&self) -> bool {
+        regs().events_in[C::INDEX].read().bits() != 0
+    }
+
+    /// Clear the IN event
+    pub fn clear(&mut self) {
+        regs().events_in[C::INDEX].reset();
+    }
+
+    /// The IN event of this channel, for use as a PPI event endpoint
+    pub fn event_in(&self) -> &gpiote::EVENTS_IN {
+        &regs().events_in[C::INDEX]
+    }
+
+    /// Disable the channel, returning it unconfigured together with the pin
+    pub fn free(self) -> (Channel<C>, Pin<Input<MODE>>) {
+        let gpiote = regs();
+        gpiote
+            .intenclr
+            .write(|w| unsafe { w.bits(1 << C::INDEX) });
+        gpiote.config[C::INDEX].write(|w| w.mode().disabled());
+        gpiote.events_in[C::INDEX].reset();
+
+        (Channel::new(), self.pin)
+    }
+}
+
+/// A GPIOTE channel driving an output pin
+pub struct TaskChannel<C, MODE> {
+    pin: Pin<Output<MODE>>,
+    _channel: PhantomData<C>,
+}
+
+impl<C, MODE> TaskChannel<C, MODE>
+where
+    C: ChannelNum,
+{
+    /// Trigger the OUT task, performing the action given at configuration
+    pub fn out(&mut self) {
+        regs().tasks_out[C::INDEX].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Trigger the SET task, driving the pin high
+    pub fn set(&mut self) {
+        regs().tasks_set[C::INDEX].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Trigger the CLR task, driving the pin low
+    pub fn clear(&mut self) {
+        regs().tasks_clr[C::INDEX].write(|w| unsafe { w.bits(1) });
+    }
+
+    /// The OUT task of this channel, for use as a PPI task endpoint
+    pub fn task_out(&self) -> &gpiote::TASKS_OUT {
+        &regs().tasks_out[C::INDEX]
+    }
+
+    /// The SET task of this channel, for use as a PPI task endpoint
+    pub fn task_set(&self) -> &gpiote::TASKS_SET {
+        &regs().tasks_set[C::INDEX]
+    }
+
+    /// The CLR task of this channel, for use as a PPI task endpoint
+    pub fn task_clr(&self) -> &gpiote::TASKS_CLR {
+        &regs().tasks_clr[C::INDEX]
+    }
+
+    /// Disable the channel, returning it unconfigured together with the pin
+    ///
+    /// The pin returns to the level of the GPIO OUT register.
+    pub fn free(self) -> (Channel<C>, Pin<Output<MODE>>) {
+        regs().config[C::INDEX].write(|w| w.mode().disabled());
+
+        (Channel::new(), self.pin)
+    }
+}
+
+/// The GPIOTE PORT event
+///
+/// The PORT event is generated from the DETECT signal, which is raised when
+/// any pin with sensing enabled is at its sensed level. Unlike the IN events
+/// it runs off the low frequency clock, which makes it the event of choice
+/// for waking up from sleep. The same sense configuration is also what wakes
+/// the chip from System OFF.
+pub struct Port {
+    _private: (),
+}
+
+impl Port {
+    /// Configure the level at which `pin` raises the DETECT signal
+    pub fn sense<MODE>(&mut self, pin: &Pin<Input<MODE>>, sense: Sense) {
+        set_sense(pin, sense);
+    }
+
+    /// Enable the GPIOTE interrupt for the PORT event
+    pub fn enable_interrupt(&mut self) {
+        regs().intenset.write(|w| unsafe { w.bits(PORT_INT_BIT) });
+    }
+
+    /// Disable the GPIOTE interrupt for the PORT event
+    pub fn disable_interrupt(&mut self) {
+        regs().intenclr.write(|w| unsafe { w.bits(PORT_INT_BIT) });
+    }
+
+    /// Has the PORT event been generated since it was last cleared?
+    pub fn is_triggered(&self) -> bool {
+        regs().events_port.read().bits() != 0
+    }
+
+    /// Clear the PORT event
+    pub fn clear(&mut self) {
+        regs().events_port.reset();
+    }
+
+    /// The PORT event, for use as a PPI event endpoint
+    pub fn event_port(&self) -> &gpiote::EVENTS_PORT {
+        &regs().events_port
+    }
+}
+
+/// Configure the level at which `pin` raises the DETECT signal
+pub(crate) fn set_sense<MODE>(pin: &Pin<Input<MODE>>, sense: Sense) {
+    // This is safe, as we restrict our access to the SENSE field of the
+    // dedicated register for a pin that we have been shown ownership of.
+    let pin_cnf = unsafe { &(*port_ptr(pin)).pin_cnf[pin.pin as usize] };
+    pin_cnf.modify(|_, w| match sense {
+        Sense::Disabled => w.sense().disabled(),
+        Sense::High => w.sense().high(),
+        Sense::Low => w.sense().low(),
+    });
+}
+
+fn port_ptr<MODE>(_pin: &Pin<MODE>) -> *const p0::RegisterBlock {
+    #[cfg(any(feature = "52810", feature = "52832"))]
+    {
+        P0::ptr()
+    }
+    #[cfg(feature = "52840")]
+    {
+        if !_pin.port {
+            P0::ptr()
+        } else {
+            P1::ptr()
+        }
+    }
+}
+
+/// The events that were pending when the interrupt was processed
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Triggered {
+    channels: u8,
+    port: bool,
+}
+
+impl Triggered {
+    /// Did the IN event of channel `C` fire?
+    pub fn channel<C: ChannelNum>(&self) -> bool {
+        self.channels & (1 << C::INDEX) != 0
+    }
+
+    /// Bit mask of the channels whose IN event fired, bit n for channel n
+    pub fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    /// Did the PORT event fire?
+    pub fn port(&self) -> bool {
+        self.port
+    }
+
+    /// Did no event fire at all?
+    pub fn is_empty(&self) -> bool {
+        self.channels == 0 && !self.port
+    }
+}
+
+/// Handle to be used from the `GPIOTE` interrupt
+pub struct Handler {
+    _private: (),
+}
+
+impl Handler {
+    /// Collect and clear all events that have their interrupt enabled
+    ///
+    /// Events without an enabled interrupt are left untouched, so channels
+    /// that are polled are not disturbed by the interrupt handler.
+    pub fn process_interrupt(&mut self) -> Triggered {
+        let gpiote = regs();
+        let enabled = gpiote.intenset.read().bits();
+
+        let mut channels = 0;
+        for i in 0..NUM_CHANNELS {
+            if enabled & (1 << i) != 0 && gpiote.events_in[i].read().bits() != 0 {
+                gpiote.events_in[i].reset();
+                channels |= 1 << i;
+            }
+        }
+
+        let port = enabled & PORT_INT_BIT != 0 && gpiote.events_port.read().bits() != 0;
+        if port {
+            gpiote.events_port.reset();
+        }
+
+        Triggered { channels, port }
+    }
+}
@@ -0,0 +1,536 @@
+//! A small wear-levelled key-value store on top of `nvmc::Flash`
+//!
+//! The store spreads its data over two or more flash pages, of which one is
+//! active at any time. Values are appended to the active page as records,
+//! the last record of a key being its current value. When the active page
+//! is full, the live records are compacted into the next page, so erases
+//! rotate over all pages.
+//!
+//! Each page starts with a sequence number, written only once the page is
+//! complete; the valid page with the highest number is the active one. Each
+//! record is a header word holding the key and length, the value padded to
+//! whole words, and a commit word written last. A record interrupted by a
+//! reset is thus never taken into account, and neither is an interrupted
+//! compaction.
+use crate::nvmc::{self, Flash, ERASED_WORD, PAGE_SIZE, WORD_SIZE};
+
+/// Key that can not be used, as it marks free space
+pub const RESERVED_KEY: u16 = 0xFFFF;
+
+/// Length marking a record that removes its key
+const TOMBSTONE: u16 = 0xFFFF;
+
+/// Value of the commit word of a complete record
+const COMMITTED: u32 = 0;
+
+/// Offset of the first record in a page
+const RECORDS_START: usize = WORD_SIZE;
+
+/// Size of the record header and commit word
+const RECORD_OVERHEAD: usize = 2 * WORD_SIZE;
+
+/// Largest value that fits in a page
+pub const MAX_VALUE_LEN: usize = PAGE_SIZE - RECORDS_START - RECORD_OVERHEAD;
+
+/// A record as found in flash
+struct Record {
+    /// Offset of the header, from the start of the page
+    offset: usize,
+    key: u16,
+    len: u16,
+    committed: bool,
+}
+
+impl Record {
+    fn data_len(&self) -> usize {
+        if self.len == TOMBSTONE {
+            0
+        } else {
+            self.len as usize
+        }
+    }
+
+    fn size(&self) -> usize {
+        record_size(self.data_len())
+    }
+}
+
+fn record_size(len: usize) -> usize {
+    RECORD_OVERHEAD + (len + WORD_SIZE - 1) / WORD_SIZE * WORD_SIZE
+}
+
+/// A key-value store on a `Flash` of at least two pages
+pub struct KvStore<F> {
+    flash: F,
+    /// Offset of the active page
+    active: usize,
+    /// Sequence number of the active page
+    seq: u32,
+    /// Offset of the free space in the active page, from the start of the page
+    free_offset: usize,
+}
+
+impl<F> KvStore<F>
+where
+    F: Flash,
+{
+    /// Mount the store, formatting the flash if it holds no valid page
+    pub fn new(flash: F) -> Result<Self, Error> {
+        if flash.capacity() < 2 * PAGE_SIZE {
+            return Err(Error::TooFewPages);
+        }
+
+        let mut store = KvStore {
+            flash,
+            active: 0,
+            seq: 0,
+            free_offset: RECORDS_START,
+        };
+
+        let mut found = false;
+        for page in (0..store.flash.capacity()).step_by(PAGE_SIZE) {
+            let seq = store.flash.read_word(page)?;
+            if seq != ERASED_WORD && (!found || seq > store.seq) {
+                found = true;
+                store.active = page;
+                store.seq = seq;
+            }
+        }
+
+        if found {
+            store.free_offset = store.find_free()?;
+        } else {
+            store.format()?;
+        }
+
+        Ok(store)
+    }
+
+    /// Erase all pages and start over with an empty store
+    pub fn format(&mut self) -> Result<(), Error> {
+        for page in (0..self.flash.capacity()).step_by(PAGE_SIZE) {
+            self.flash.erase_page(page)?;
+        }
+        self.flash.write(0, &[0])?;
+
+        self.active = 0;
+        self.seq = 0;
+        self.free_offset = RECORDS_START;
+
+        Ok(())
+    }
+
+    /// Return the underlying flash
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    /// Read the value of `key` into `buf`
+    ///
+    /// Returns the length of the value, or `None` if the key is not set.
+    pub fn get(&self, key: u16, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let mut found = None;
+        let mut offset = RECORDS_START;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            if record.committed && record.key == key {
+                found = Some(record.offset);
+            }
+            offset += record.size();
+        }
+
+        let record = match found {
+            Some(offset) => self.record_at(self.active, offset)?.unwrap(),
+            None => return Ok(None),
+        };
+        if record.len == TOMBSTONE {
+            return Ok(None);
+        }
+
+        let len = record.data_len();
+        if buf.len() < len {
+            return Err(Error::BufferTooSmall);
+        }
+        self.flash
+            .read(self.active + record.offset + WORD_SIZE, &mut buf[..len])?;
+
+        Ok(Some(len))
+    }
+
+    /// Set the value of `key`
+    pub fn set(&mut self, key: u16, value: &[u8]) -> Result<(), Error> {
+        if key == RESERVED_KEY {
+            return Err(Error::KeyReserved);
+        }
+        if value.len() > MAX_VALUE_LEN {
+            return Err(Error::ValueTooLarge);
+        }
+
+        self.append(key, value.len() as u16, value)
+    }
+
+    /// Remove `key` from the store
+    pub fn remove(&mut self, key: u16) -> Result<(), Error> {
+        if key == RESERVED_KEY {
+            return Err(Error::KeyReserved);
+        }
+
+        self.append(key, TOMBSTONE, &[])
+    }
+
+    /// Number of bytes left in the active page for records
+    ///
+    /// More space may become available when the page is compacted.
+    pub fn free_space(&self) -> usize {
+        PAGE_SIZE - self.free_offset
+    }
+
+    fn append(&mut self, key: u16, len: u16, value: &[u8]) -> Result<(), Error> {
+        if self.free_offset + record_size(value.len()) > PAGE_SIZE {
+            self.compact(record_size(value.len()))?;
+        }
+
+        let offset = self.active + self.free_offset;
+        self.write_record(offset, key, len, value)?;
+        self.free_offset += record_size(value.len());
+
+        Ok(())
+    }
+
+    fn write_record(
+        &mut self,
+        offset: usize,
+        key: u16,
+        len: u16,
+        value: &[u8],
+    ) -> Result<(), Error> {
+        self.flash
+            .write(offset, &[u32::from(key) | u32::from(len) << 16])?;
+
+        // Pack the value in words, padding the last one with erased bytes
+        let mut data_offset = offset + WORD_SIZE;
+        for chunk in value.chunks(WORD_SIZE) {
+            let mut bytes = [0xFF; WORD_SIZE];
+            bytes[..chunk.len()].copy_from_slice(chunk);
+            self.flash.write(data_offset, &[u32::from_le_bytes(bytes)])?;
+            data_offset += WORD_SIZE;
+        }
+
+        self.flash.write(data_offset, &[COMMITTED])?;
+
+        Ok(())
+    }
+
+    /// Copy the live records of the active page to the next page, which
+    /// becomes active, leaving room for a record of `needed` bytes
+    fn compact(&mut self, needed: usize) -> Result<(), Error> {
+        // Find out whether everything fits before touching any page
+        let mut live = 0;
+        let mut offset = RECORDS_START;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            if self.is_live(&record)? {
+                live += record.size();
+            }
+            offset += record.size();
+        }
+        if RECORDS_START + live + needed > PAGE_SIZE {
+            return Err(Error::StoreFull);
+        }
+
+        let next = (self.active + PAGE_SIZE) % self.flash.capacity();
+        self.flash.erase_page(next)?;
+
+        let mut to = next + RECORDS_START;
+        let mut offset = RECORDS_START;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            if self.is_live(&record)? {
+                for i in (0..record.size()).step_by(WORD_SIZE) {
+                    let word = self.flash.read_word(self.active + record.offset + i)?;
+                    self.flash.write(to + i, &[word])?;
+                }
+                to += record.size();
+            }
+            offset += record.size();
+        }
+
+        // Only now that the page is complete does it become valid
+        self.flash.write(next, &[self.seq.wrapping_add(1)])?;
+
+        self.active = next;
+        self.seq = self.seq.wrapping_add(1);
+        self.free_offset = to - next;
+
+        Ok(())
+    }
+
+    /// Is `record` the current value of its key?
+    fn is_live(&self, record: &Record) -> Result<bool, Error> {
+        if !record.committed || record.len == TOMBSTONE {
+            return Ok(false);
+        }
+
+        let mut offset = record.offset + record.size();
+        while let Some(later) = self.record_at(self.active, offset)? {
+            if later.committed && later.key == record.key {
+                return Ok(false);
+            }
+            offset += later.size();
+        }
+
+        Ok(true)
+    }
+
+    /// Offset of the free space in the active page
+    fn find_free(&self) -> Result<usize, Error> {
+        let mut offset = RECORDS_START;
+        while let Some(record) = self.record_at(self.active, offset)? {
+            offset += record.size();
+        }
+
+        // A header damaged by a reset may claim more than is left, in which
+        // case the page is treated as full
+        if offset + WORD_SIZE <= PAGE_SIZE
+            && self.flash.read_word(self.active + offset)? != ERASED_WORD
+        {
+            return Ok(PAGE_SIZE);
+        }
+
+        Ok(offset.min(PAGE_SIZE))
+    }
+
+    /// The record at `offset` in `page`, `None` at the free space
+    fn record_at(&self, page: usize, offset: usize) -> Result<Option<Record>, Error> {
+        if offset + RECORD_OVERHEAD > PAGE_SIZE {
+            return Ok(None);
+        }
+
+        let header = self.flash.read_word(page + offset)?;
+        if header == ERASED_WORD {
+            return Ok(None);
+        }
+
+        let mut record = Record {
+            offset,
+            key: header as u16,
+            len: (header >> 16) as u16,
+            committed: false,
+        };
+        if offset + record.size() > PAGE_SIZE {
+            return Ok(None);
+        }
+        let commit = offset + record.size() - WORD_SIZE;
+        record.committed = self.flash.read_word(page + commit)? == COMMITTED;
+
+        Ok(Some(record))
+    }
+}
+
+/// Error types associated with the key-value store
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The underlying flash failed
+    Flash(nvmc::Error),
+    /// The store needs at least two pages of flash
+    TooFewPages,
+    /// `RESERVED_KEY` can not be used
+    KeyReserved,
+    /// The value is larger than `MAX_VALUE_LEN`
+    ValueTooLarge,
+    /// The buffer is too small for the value
+    BufferTooSmall,
+    /// The live values do not fit in a page
+    StoreFull,
+}
+
+impl From<nvmc::Error> for Error {
+    fn from(e: nvmc::Error) -> Self {
+        Error::Flash(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nvmc::RamFlash;
+
+    const PAGES: usize = 3;
+    const WORDS: usize = PAGES * PAGE_SIZE / WORD_SIZE;
+
+    fn mount(mem: &mut [u32]) -> KvStore<RamFlash<'_>> {
+        KvStore::new(RamFlash::new(mem).unwrap()).unwrap()
+    }
+
+    fn get(store: &KvStore<RamFlash<'_>>, key: u16) -> Option<[u8; 4]> {
+        let mut buf = [0; 4];
+        store.get(key, &mut buf).unwrap().map(|len| {
+            assert_eq!(len, 4);
+            buf
+        })
+    }
+
+    #[test]
+    fn set_get_overwrite_remove() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+
+        assert_eq!(get(&store, 1), None);
+        store.set(1, b"abcd").unwrap();
+        store.set(2, b"efgh").unwrap();
+        assert_eq!(get(&store, 1), Some(*b"abcd"));
+        assert_eq!(get(&store, 2), Some(*b"efgh"));
+
+        store.set(1, b"ijkl").unwrap();
+        assert_eq!(get(&store, 1), Some(*b"ijkl"));
+
+        store.remove(1).unwrap();
+        assert_eq!(get(&store, 1), None);
+        assert_eq!(get(&store, 2), Some(*b"efgh"));
+
+        // Odd lengths are padded, and the padding is not returned
+        store.set(3, b"xyz").unwrap();
+        let mut buf = [0; 8];
+        assert_eq!(store.get(3, &mut buf), Ok(Some(3)));
+        assert_eq!(&buf[..3], b"xyz");
+        assert_eq!(store.get(3, &mut buf[..2]), Err(Error::BufferTooSmall));
+
+        // Everything is still there after a power cycle
+        let store = mount(&mut mem);
+        assert_eq!(get(&store, 1), None);
+        assert_eq!(get(&store, 2), Some(*b"efgh"));
+    }
+
+    #[test]
+    fn invalid_arguments() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+
+        assert_eq!(store.set(RESERVED_KEY, b""), Err(Error::KeyReserved));
+        assert_eq!(store.remove(RESERVED_KEY), Err(Error::KeyReserved));
+        let value = [0; MAX_VALUE_LEN + 1];
+        assert_eq!(store.set(1, &value), Err(Error::ValueTooLarge));
+        store.set(1, &value[..MAX_VALUE_LEN]).unwrap();
+
+        let mut page = [ERASED_WORD; PAGE_SIZE / WORD_SIZE];
+        let flash = RamFlash::new(&mut page).unwrap();
+        assert_eq!(KvStore::new(flash).err(), Some(Error::TooFewPages));
+    }
+
+    #[test]
+    fn compaction_rotates_over_pages() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+        store.set(1, b"keep").unwrap();
+
+        let value = [0x55; 100];
+        let mut visited = [false; PAGES];
+        for i in 0..10 * PAGE_SIZE / record_size(value.len()) {
+            store.set(2, &value).unwrap();
+            store.set(3, &(i as u32).to_le_bytes()).unwrap();
+            visited[store.active / PAGE_SIZE] = true;
+        }
+
+        // Every page has been erased and used in turn
+        assert_eq!(visited, [true; PAGES]);
+        assert!(store.seq >= 10);
+
+        // Compaction kept only the live records
+        assert_eq!(get(&store, 1), Some(*b"keep"));
+        let mut buf = [0; 100];
+        assert_eq!(store.get(2, &mut buf), Ok(Some(100)));
+        assert_eq!(&buf[..], &value[..]);
+
+        let last = get(&store, 3).unwrap();
+        let store = mount(&mut mem);
+        assert_eq!(get(&store, 1), Some(*b"keep"));
+        assert_eq!(get(&store, 3), Some(last));
+    }
+
+    #[test]
+    fn store_full() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+
+        let value = [0; 1000];
+        let mut key = 0;
+        let err = loop {
+            match store.set(key, &value) {
+                Ok(()) => key += 1,
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(err, Error::StoreFull);
+
+        // The values written so far are intact, and removing one makes room
+        for k in 0..key {
+            assert_eq!(store.get(k, &mut [0; 1000]), Ok(Some(1000)));
+        }
+        store.remove(0).unwrap();
+        store.set(key, &value).unwrap();
+    }
+
+    #[test]
+    fn partial_record_is_ignored() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+        store.set(1, b"abcd").unwrap();
+
+        // A reset hits after the header and value of a new value for key 1
+        // were written, before the commit word
+        let offset = store.active + store.free_offset;
+        let mut flash = store.free();
+        flash.write(offset, &[1 | 4 << 16]).unwrap();
+        flash.write(offset + WORD_SIZE, &[0x3433_3231]).unwrap();
+
+        let mut store = mount(&mut mem);
+        assert_eq!(get(&store, 1), Some(*b"abcd"));
+
+        // New records go after the partial one
+        store.set(1, b"efgh").unwrap();
+        assert_eq!(get(&store, 1), Some(*b"efgh"));
+        let store = mount(&mut mem);
+        assert_eq!(get(&store, 1), Some(*b"efgh"));
+    }
+
+    #[test]
+    fn partial_header_fills_page() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+        store.set(1, b"abcd").unwrap();
+
+        // A damaged header claiming more than the rest of the page
+        let offset = store.active + store.free_offset;
+        let mut flash = store.free();
+        flash.write(offset, &[2 | 0x7000 << 16]).unwrap();
+
+        // The page is taken as full, so the next value compacts it
+        let mut store = mount(&mut mem);
+        assert_eq!(store.free_space(), 0);
+        store.set(2, b"efgh").unwrap();
+        assert_eq!(store.active, PAGE_SIZE);
+        assert_eq!(get(&store, 1), Some(*b"abcd"));
+        assert_eq!(get(&store, 2), Some(*b"efgh"));
+    }
+
+    #[test]
+    fn interrupted_compaction_is_ignored() {
+        let mut mem = [ERASED_WORD; WORDS];
+        let mut store = mount(&mut mem);
+        store.set(1, b"abcd").unwrap();
+
+        // A reset hits while the record was copied to the next page, before
+        // its sequence number was written
+        let from = store.active + RECORDS_START;
+        let to = store.active + PAGE_SIZE + RECORDS_START;
+        let mut flash = store.free();
+        let header = flash.read_word(from).unwrap();
+        flash.write(to, &[header]).unwrap();
+
+        let mut store = mount(&mut mem);
+        assert_eq!(store.active, 0);
+        assert_eq!(get(&store, 1), Some(*b"abcd"));
+
+        // The half written page is erased by the next compaction
+        store.compact(0).unwrap();
+        assert_eq!(store.active, PAGE_SIZE);
+        assert_eq!(get(&store, 1), Some(*b"abcd"));
+    }
+}
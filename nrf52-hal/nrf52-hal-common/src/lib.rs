@@ -13,7 +13,16 @@ pub use nrf52840_pac as target;
 
 pub mod clocks;
 pub mod delay;
+pub mod ecb;
+pub mod framing;
 pub mod gpio;
+pub mod gpiote;
+pub mod kvstore;
+pub mod monotonic;
+pub mod nvmc;
+pub mod power;
+pub mod ppi;
+pub mod pwm;
 pub mod rng;
 pub mod rtc;
 pub mod saadc;
@@ -23,12 +32,17 @@ pub mod time;
 pub mod timer;
 pub mod twim;
 pub mod uarte;
+pub mod wdt;
 
 pub mod prelude {
     pub use crate::hal::prelude::*;
 
     pub use crate::clocks::ClocksExt;
     pub use crate::gpio::GpioExt;
+    pub use crate::gpiote::GpioteExt;
+    pub use crate::power::PowerExt;
+    pub use crate::ppi::PpiExt;
+    pub use crate::pwm::PwmExt;
     pub use crate::rng::RngExt;
     pub use crate::rtc::RtcExt;
     pub use crate::saadc::SaadcExt;
@@ -37,6 +51,7 @@ pub mod prelude {
     pub use crate::timer::TimerExt;
     pub use crate::twim::TwimExt;
     pub use crate::uarte::UarteExt;
+    pub use crate::wdt::WdtExt;
 }
 
 /// Length of Nordic EasyDMA differs for MCUs
@@ -66,4 +81,7 @@ pub use crate::saadc::Saadc;
 pub use crate::spim::Spim;
 pub use crate::timer::Timer;
 pub use crate::twim::Twim;
-pub use crate::uarte::{DMAPool, RXError, TXQSize, Uarte, UarteRX, UarteTX, DMA_SIZE};
+pub use crate::uarte::{
+    Chunk, DMAPool, IdleTimeout, NoIdleTimeout, RXError, RXQSize, TXQSize, Uarte, UarteRX, UarteTX,
+    DMA_SIZE,
+};
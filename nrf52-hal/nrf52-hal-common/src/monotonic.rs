@@ -0,0 +1,476 @@
+//! A 64-bit monotonic clock on an RTC peripheral
+//!
+//! The RTC counts the 32.768 kHz low frequency clock in 24 bits, wrapping
+//! every 512 seconds. `Monotonic` counts the overflows to extend it to 64
+//! bits, and uses compare register 0 to raise an interrupt at a given
+//! `Instant`. Unlike the DWT cycle counter, the RTC keeps running while the
+//! CPU sleeps, so alarms can wake the system from low-power sleep.
+//!
+//! The low frequency clock must be started, see `Clocks::start_lfclk`, and
+//! the interrupt of the RTC must call `Monotonic::process_interrupt` at
+//! least once per overflow period.
+//!
+//! RTFM 0.4 hard-codes the DWT cycle counter for `schedule`, with no way to
+//! plug in another monotonic timer. `TimerQueue` takes the place of its
+//! timer queue on this clock: tasks, or any other message, are scheduled at
+//! an `Instant`, and the RTC interrupt spawns them once they are due, see
+//! `examples/app10.rs`.
+use core::cmp::Ordering;
+use core::ops::{Add, AddAssign, Sub, SubAssign};
+
+use heapless::{
+    binary_heap::{BinaryHeap, Min},
+    ArrayLength,
+};
+
+use crate::rtc::{Rtc, RtcCompareReg, RtcExt, RtcInterrupt, Started, Stopped, MIN_COMPARE_TICKS};
+use crate::time::{MicroSeconds, MilliSeconds, Seconds};
+
+/// Frequency of the monotonic clock
+pub const TICK_HZ: u32 = 32_768;
+
+/// Mask of the RTC counter
+const COUNTER_MASK: u32 = (1 << 24) - 1;
+
+/// Alarms closer than this number of ticks may not trigger the RTC compare
+/// event
+const MIN_ALARM_TICKS: u64 = MIN_COMPARE_TICKS as u64;
+
+/// A point in time, in ticks of the monotonic clock since it was started
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Instant(u64);
+
+impl Instant {
+    /// The instant `ticks` ticks after the clock was started
+    pub fn from_ticks(ticks: u64) -> Self {
+        Instant(ticks)
+    }
+
+    /// Ticks since the clock was started
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// Time elapsed from `earlier` to `self`, `None` if `earlier` is later
+    pub fn checked_duration_since(&self, earlier: Instant) -> Option<Duration> {
+        self.0.checked_sub(earlier.0).map(Duration)
+    }
+
+    /// Time elapsed from `earlier` to `self`, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: Instant) -> Duration {
+        self.checked_duration_since(earlier).unwrap_or(Duration(0))
+    }
+}
+
+impl Add<Duration> for Instant {
+    type Output = Instant;
+
+    fn add(self, rhs: Duration) -> Instant {
+        Instant(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign<Duration> for Instant {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.0 += rhs.0;
+    }
+}
+
+/// Saturates at the instant the clock was started
+impl Sub<Duration> for Instant {
+    type Output = Instant;
+
+    fn sub(self, rhs: Duration) -> Instant {
+        Instant(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl SubAssign<Duration> for Instant {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+/// Saturates at zero, like `duration_since`
+impl Sub<Instant> for Instant {
+    type Output = Duration;
+
+    fn sub(self, rhs: Instant) -> Duration {
+        self.duration_since(rhs)
+    }
+}
+
+/// A span of time, in ticks of the monotonic clock
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Duration(u64);
+
+impl Duration {
+    /// The duration of `ticks` ticks of the monotonic clock
+    pub fn from_ticks(ticks: u64) -> Self {
+        Duration(ticks)
+    }
+
+    /// The duration closest to `us` microseconds
+    pub fn from_micros(us: u64) -> Self {
+        Duration((us * u64::from(TICK_HZ) + 500_000) / 1_000_000)
+    }
+
+    /// The duration closest to `ms` milliseconds
+    pub fn from_millis(ms: u64) -> Self {
+        Duration((ms * u64::from(TICK_HZ) + 500) / 1_000)
+    }
+
+    /// The duration of `s` seconds
+    pub fn from_secs(s: u64) -> Self {
+        Duration(s * u64::from(TICK_HZ))
+    }
+
+    /// The duration in ticks of the monotonic clock
+    pub fn ticks(&self) -> u64 {
+        self.0
+    }
+
+    /// The duration in whole microseconds, rounded down
+    pub fn as_micros(&self) -> u64 {
+        self.0 * 1_000_000 / u64::from(TICK_HZ)
+    }
+
+    /// The duration in whole milliseconds, rounded down
+    pub fn as_millis(&self) -> u64 {
+        self.0 * 1_000 / u64::from(TICK_HZ)
+    }
+
+    /// The duration in whole seconds, rounded down
+    pub fn as_secs(&self) -> u64 {
+        self.0 / u64::from(TICK_HZ)
+    }
+
+    /// The sum of both durations, `None` on overflow
+    pub fn checked_add(self, rhs: Duration) -> Option<Duration> {
+        self.0.checked_add(rhs.0).map(Duration)
+    }
+
+    /// The difference of both durations, `None` if `rhs` is longer
+    pub fn checked_sub(self, rhs: Duration) -> Option<Duration> {
+        self.0.checked_sub(rhs.0).map(Duration)
+    }
+}
+
+impl From<MicroSeconds> for Duration {
+    fn from(us: MicroSeconds) -> Self {
+        Duration::from_micros(u64::from(us.0))
+    }
+}
+
+impl From<MilliSeconds> for Duration {
+    fn from(ms: MilliSeconds) -> Self {
+        Duration::from_millis(u64::from(ms.0))
+    }
+}
+
+impl From<Seconds> for Duration {
+    fn from(s: Seconds) -> Self {
+        Duration::from_secs(u64::from(s.0))
+    }
+}
+
+impl Add for Duration {
+    type Output = Duration;
+
+    fn add(self, rhs: Duration) -> Duration {
+        Duration(self.0 + rhs.0)
+    }
+}
+
+impl AddAssign for Duration {
+    fn add_assign(&mut self, rhs: Duration) {
+        self.0 += rhs.0;
+    }
+}
+
+/// Saturates at zero
+impl Sub for Duration {
+    type Output = Duration;
+
+    fn sub(self, rhs: Duration) -> Duration {
+        Duration(self.0.saturating_sub(rhs.0))
+    }
+}
+
+impl SubAssign for Duration {
+    fn sub_assign(&mut self, rhs: Duration) {
+        *self = *self - rhs;
+    }
+}
+
+/// A 64-bit monotonic clock with an alarm
+pub struct Monotonic<T> {
+    rtc: Rtc<T, Started>,
+    /// The instant the alarm is set for
+    alarm: Option<Instant>,
+}
+
+impl<T> Monotonic<T>
+where
+    T: RtcExt,
+{
+    /// Start the clock on `rtc`, from zero
+    pub fn new(rtc: Rtc<T, Stopped>) -> Self {
+        let mut rtc = rtc;
+        rtc.set_prescaler(0).unwrap();
+        rtc.get_event_triggered(RtcInterrupt::Compare0, true);
+        rtc.enable_interrupt(RtcInterrupt::Overflow);
+
+        let mut rtc = rtc.enable_counter();
+        rtc.clear_counter();
+
+        Monotonic { rtc, alarm: None }
+    }
+
+    /// Stop the clock, returning the RTC
+    pub fn free(mut self) -> Rtc<T, Stopped> {
+        self.rtc.disable_interrupt(RtcInterrupt::Overflow);
+        self.rtc.disable_interrupt(RtcInterrupt::Compare0);
+        self.rtc.disable_counter()
+    }
+
+    /// The current time
+    pub fn now(&mut self) -> Instant {
+        Instant(self.rtc.uptime_ticks())
+    }
+
+    /// Time elapsed since `earlier`
+    pub fn elapsed(&mut self, earlier: Instant) -> Duration {
+        self.now().duration_since(earlier)
+    }
+
+    /// Raise the RTC interrupt at `at`, replacing any earlier alarm
+    ///
+    /// Returns `false`, without setting the alarm, if `at` is too close or
+    /// already in the past, in which case the caller should act right away.
+    pub fn set_alarm(&mut self, at: Instant) -> bool {
+        if at.0 < self.now().0 + MIN_ALARM_TICKS {
+            self.cancel_alarm();
+            return false;
+        }
+
+        // An alarm beyond the current counter period matches early, which is
+        // caught by `process_interrupt`
+        self.rtc
+            .set_compare(RtcCompareReg::Compare0, at.0 as u32 & COUNTER_MASK)
+            .unwrap();
+        self.rtc.get_event_triggered(RtcInterrupt::Compare0, true);
+        self.rtc.enable_interrupt(RtcInterrupt::Compare0);
+        self.alarm = Some(at);
+
+        // The alarm may have passed while it was being set
+        if at.0 < self.now().0 + MIN_ALARM_TICKS {
+            self.cancel_alarm();
+            return false;
+        }
+
+        true
+    }
+
+    /// Remove the alarm
+    pub fn cancel_alarm(&mut self) {
+        self.rtc.disable_interrupt(RtcInterrupt::Compare0);
+        self.rtc.get_event_triggered(RtcInterrupt::Compare0, true);
+        self.alarm = None;
+    }
+
+    /// The instant the alarm is set for
+    pub fn alarm(&self) -> Option<Instant> {
+        self.alarm
+    }
+
+    /// Handle the RTC interrupt
+    ///
+    /// Returns `true` when the alarm is due, which also removes it.
+    pub fn process_interrupt(&mut self) -> bool {
+        self.rtc.process_overflow();
+
+        if !self.rtc.get_event_triggered(RtcInterrupt::Compare0, true) {
+            return false;
+        }
+
+        let now = self.now();
+        match self.alarm {
+            Some(at) if now >= at => {
+                self.cancel_alarm();
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// A message waiting in a `TimerQueue`
+pub struct Scheduled<T> {
+    at: Instant,
+    msg: T,
+}
+
+impl<T> PartialEq for Scheduled<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl<T> Eq for Scheduled<T> {}
+
+impl<T> PartialOrd for Scheduled<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Scheduled<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.at.cmp(&other.at)
+    }
+}
+
+/// A queue of up to `N` messages, each handed out once its `Instant` has
+/// passed
+///
+/// The alarm of the `Monotonic` clock is kept set for the earliest message,
+/// so it must not be used for anything else. In the RTC interrupt, once
+/// `Monotonic::process_interrupt` has returned `true`, call `dequeue` until
+/// it returns `None`.
+pub struct TimerQueue<T, N>
+where
+    N: ArrayLength<Scheduled<T>>,
+{
+    queue: BinaryHeap<Scheduled<T>, N, Min>,
+}
+
+impl<T, N> TimerQueue<T, N>
+where
+    N: ArrayLength<Scheduled<T>>,
+{
+    /// Create an empty queue
+    pub fn new() -> Self {
+        TimerQueue {
+            queue: BinaryHeap::new(),
+        }
+    }
+
+    /// Queue `msg` to be handed out at `at`
+    ///
+    /// A message scheduled in the past is handed out as soon as possible.
+    /// Returns the message back if the queue is full.
+    pub fn schedule<R>(&mut self, mono: &mut Monotonic<R>, at: Instant, msg: T) -> Result<(), T>
+    where
+        R: RtcExt,
+    {
+        let earliest = match self.queue.peek() {
+            Some(head) => at < head.at,
+            None => true,
+        };
+        self.queue
+            .push(Scheduled { at, msg })
+            .map_err(|scheduled| scheduled.msg)?;
+
+        if earliest {
+            set_alarm(mono, at);
+        }
+        Ok(())
+    }
+
+    /// Hand out the next message that is due, setting the alarm for the
+    /// following one
+    pub fn dequeue<R>(&mut self, mono: &mut Monotonic<R>) -> Option<T>
+    where
+        R: RtcExt,
+    {
+        let at = match self.queue.peek() {
+            Some(head) => head.at,
+            None => {
+                mono.cancel_alarm();
+                return None;
+            }
+        };
+
+        if at <= mono.now() {
+            self.queue.pop().map(|scheduled| scheduled.msg)
+        } else {
+            set_alarm(mono, at);
+            None
+        }
+    }
+
+    /// Number of queued messages
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    /// Is no message queued?
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+impl<T, N> Default for TimerQueue<T, N>
+where
+    N: ArrayLength<Scheduled<T>>,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Set the alarm for `at`, or for the earliest instant the RTC can still
+/// match if `at` is too close or has passed
+fn set_alarm<R>(mono: &mut Monotonic<R>, at: Instant)
+where
+    R: RtcExt,
+{
+    let mut at = at;
+    while !mono.set_alarm(at) {
+        at = mono.now() + Duration(MIN_ALARM_TICKS);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saturating_sub() {
+        let early = Instant::from_ticks(10);
+        let late = Instant::from_ticks(30);
+        let short = Duration::from_ticks(5);
+        let long = Duration::from_ticks(20);
+
+        assert_eq!(late - early, long);
+        assert_eq!(early - late, Duration::from_ticks(0));
+        assert_eq!(early - long, Instant::from_ticks(0));
+        assert_eq!(late - long, early);
+        assert_eq!(short - long, Duration::from_ticks(0));
+        assert_eq!(long - short, Duration::from_ticks(15));
+
+        let mut instant = early;
+        instant -= long;
+        assert_eq!(instant, Instant::from_ticks(0));
+
+        let mut duration = short;
+        duration -= long;
+        assert_eq!(duration, Duration::from_ticks(0));
+    }
+
+    #[test]
+    fn scheduled_order() {
+        let mut queue: BinaryHeap<Scheduled<u8>, heapless::consts::U4, Min> = BinaryHeap::new();
+        for (at, msg) in [(30, 0), (10, 1), (20, 2), (10, 3)].iter() {
+            let at = Instant::from_ticks(*at);
+            queue.push(Scheduled { at, msg: *msg }).ok().unwrap();
+        }
+
+        let mut ticks = [0; 4];
+        for t in ticks.iter_mut() {
+            *t = queue.pop().unwrap().at.ticks();
+        }
+        assert_eq!(ticks, [10, 10, 20, 30]);
+    }
+}
@@ -0,0 +1,279 @@
+//! HAL interface to the NVMC peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 11
+//! - nrf52840: Section 4.3
+//!
+//! The NVMC driver owns a page aligned region of flash, typically reserved
+//! for data in the linker script, and offers word aligned writes, page erases
+//! and reads within that region. Offsets are given in bytes from the start of
+//! the region.
+//!
+//! With a `STORAGE` region and the `_storage_start`/`_storage_end` symbols
+//! defined in `memory.x`, the region is obtained with:
+//!
+//! ```ignore
+//! extern "C" {
+//!     static mut _storage_start: u32;
+//!     static mut _storage_end: u32;
+//! }
+//!
+//! let storage = unsafe {
+//!     let start = &mut _storage_start as *mut u32;
+//!     let len = (&_storage_end as *const u32 as usize - start as usize) / 4;
+//!     core::slice::from_raw_parts_mut(start, len)
+//! };
+//! let nvmc = Nvmc::new(device.NVMC, storage).unwrap();
+//! ```
+//!
+//! The same operations are described by the `Flash` trait, which is also
+//! implemented by `RamFlash`, an in-memory simulator with the semantics of
+//! NOR flash. Storage built on top of `Flash`, such as `kvstore::KvStore`,
+//! can thus be exercised without hardware.
+use core::ptr;
+use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
+
+use crate::target::NVMC;
+
+/// Size of a flash page in bytes
+pub const PAGE_SIZE: usize = 4096;
+
+/// Size of a flash word in bytes, the unit of writes
+pub const WORD_SIZE: usize = 4;
+
+/// Value of an erased flash word
+pub const ERASED_WORD: u32 = 0xFFFF_FFFF;
+
+/// Word aligned, page erasable non-volatile storage
+pub trait Flash {
+    /// Size of the storage in bytes, a multiple of `PAGE_SIZE`
+    fn capacity(&self) -> usize;
+
+    /// Read `buf.len()` bytes starting at `offset`
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Write `words` starting at the word aligned `offset`
+    ///
+    /// Writing can only clear bits, so the words written to should be erased.
+    fn write(&mut self, offset: usize, words: &[u32]) -> Result<(), Error>;
+
+    /// Erase the page starting at the page aligned `offset`
+    fn erase_page(&mut self, offset: usize) -> Result<(), Error>;
+
+    /// Read the word at the word aligned `offset`
+    fn read_word(&self, offset: usize) -> Result<u32, Error> {
+        let mut buf = [0; WORD_SIZE];
+        if offset % WORD_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+        self.read(offset, &mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+fn check_write(capacity: usize, offset: usize, words: &[u32]) -> Result<(), Error> {
+    if offset % WORD_SIZE != 0 {
+        return Err(Error::Unaligned);
+    }
+    if offset + words.len() * WORD_SIZE > capacity {
+        return Err(Error::OutOfBounds);
+    }
+    Ok(())
+}
+
+fn check_erase(capacity: usize, offset: usize) -> Result<(), Error> {
+    if offset % PAGE_SIZE != 0 {
+        return Err(Error::Unaligned);
+    }
+    if offset >= capacity {
+        return Err(Error::OutOfBounds);
+    }
+    Ok(())
+}
+
+fn check_read(capacity: usize, offset: usize, buf: &[u8]) -> Result<(), Error> {
+    if offset + buf.len() > capacity {
+        return Err(Error::OutOfBounds);
+    }
+    Ok(())
+}
+
+/// Interface to the NVMC peripheral, restricted to a region of flash
+pub struct Nvmc {
+    nvmc: NVMC,
+    storage: &'static mut [u32],
+}
+
+impl Nvmc {
+    /// Take control of the NVMC for `storage`
+    ///
+    /// `storage` must start on a page boundary and span whole pages. It is
+    /// usually a region reserved in `memory.x` and obtained from the symbols
+    /// defined there, as it must not hold any code or data of the program.
+    pub fn new(nvmc: NVMC, storage: &'static mut [u32]) -> Result<Self, Error> {
+        if storage.as_ptr() as usize % PAGE_SIZE != 0
+            || (storage.len() * WORD_SIZE) % PAGE_SIZE != 0
+        {
+            return Err(Error::Unaligned);
+        }
+
+        Ok(Nvmc { nvmc, storage })
+    }
+
+    /// Return the raw interface to the NVMC and the storage region
+    pub fn free(self) -> (NVMC, &'static mut [u32]) {
+        (self.nvmc, self.storage)
+    }
+
+    fn wait_ready(&self) {
+        while self.nvmc.ready.read().bits() == 0 {}
+    }
+
+    fn enable_read(&self) {
+        self.nvmc.config.write(|w| w.wen().ren());
+        self.wait_ready();
+    }
+
+    fn enable_write(&self) {
+        self.nvmc.config.write(|w| w.wen().wen());
+        self.wait_ready();
+    }
+
+    fn enable_erase(&self) {
+        self.nvmc.config.write(|w| w.wen().een());
+        self.wait_ready();
+    }
+}
+
+impl Flash for Nvmc {
+    fn capacity(&self) -> usize {
+        self.storage.len() * WORD_SIZE
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        check_read(self.capacity(), offset, buf)?;
+
+        let base = self.storage.as_ptr() as *const u8;
+        for (i, b) in buf.iter_mut().enumerate() {
+            // NOTE(unsafe) in bounds of the storage region, checked above
+            *b = unsafe { ptr::read_volatile(base.add(offset + i)) };
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, words: &[u32]) -> Result<(), Error> {
+        check_write(self.capacity(), offset, words)?;
+
+        self.enable_write();
+        let base = self.storage.as_mut_ptr();
+        for (i, word) in words.iter().enumerate() {
+            // NOTE(unsafe) in bounds of the storage region, checked above
+            unsafe { ptr::write_volatile(base.add(offset / WORD_SIZE + i), *word) };
+            self.wait_ready();
+        }
+        self.enable_read();
+
+        // Make sure reads of the region are not moved before the write
+        compiler_fence(SeqCst);
+
+        Ok(())
+    }
+
+    fn erase_page(&mut self, offset: usize) -> Result<(), Error> {
+        check_erase(self.capacity(), offset)?;
+
+        self.enable_erase();
+        let address = self.storage.as_ptr() as u32 + offset as u32;
+        self.nvmc.erasepage().write(|w| unsafe { w.bits(address) });
+        self.wait_ready();
+        self.enable_read();
+
+        // Make sure reads of the region are not moved before the erase
+        compiler_fence(SeqCst);
+
+        Ok(())
+    }
+}
+
+/// In-memory flash simulator
+///
+/// Behaves like NOR flash: erasing sets a page to all ones and writing can
+/// only clear bits, so writing to a word that was not erased is detected as
+/// `Error::NotErased`.
+pub struct RamFlash<'a> {
+    mem: &'a mut [u32],
+}
+
+impl<'a> RamFlash<'a> {
+    /// Use `mem` as flash, its length must span whole pages
+    ///
+    /// The contents of `mem` are kept, so a simulated power cycle is a
+    /// matter of creating a new `RamFlash` on the same memory.
+    pub fn new(mem: &'a mut [u32]) -> Result<Self, Error> {
+        if (mem.len() * WORD_SIZE) % PAGE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        Ok(RamFlash { mem })
+    }
+
+    /// Return the backing memory
+    pub fn free(self) -> &'a mut [u32] {
+        self.mem
+    }
+}
+
+impl<'a> Flash for RamFlash<'a> {
+    fn capacity(&self) -> usize {
+        self.mem.len() * WORD_SIZE
+    }
+
+    fn read(&self, offset: usize, buf: &mut [u8]) -> Result<(), Error> {
+        check_read(self.capacity(), offset, buf)?;
+
+        for (i, b) in buf.iter_mut().enumerate() {
+            let byte = offset + i;
+            *b = self.mem[byte / WORD_SIZE].to_le_bytes()[byte % WORD_SIZE];
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: usize, words: &[u32]) -> Result<(), Error> {
+        check_write(self.capacity(), offset, words)?;
+
+        let start = offset / WORD_SIZE;
+        for (cell, word) in self.mem[start..start + words.len()].iter_mut().zip(words) {
+            if *cell & *word != *word {
+                return Err(Error::NotErased);
+            }
+            *cell &= *word;
+        }
+
+        Ok(())
+    }
+
+    fn erase_page(&mut self, offset: usize) -> Result<(), Error> {
+        check_erase(self.capacity(), offset)?;
+
+        let start = offset / WORD_SIZE;
+        for cell in &mut self.mem[start..start + PAGE_SIZE / WORD_SIZE] {
+            *cell = ERASED_WORD;
+        }
+
+        Ok(())
+    }
+}
+
+/// Error types associated with flash storage
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// Offset or region not aligned to a word (writes) or page (erases)
+    Unaligned,
+    /// Access beyond the end of the storage region
+    OutOfBounds,
+    /// Write would need to set bits that are cleared (simulator only)
+    NotErased,
+}
@@ -0,0 +1,444 @@
+//! HAL interface to the POWER peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 17
+//! - nrf52840: Section 5.3
+//!
+//! Besides supervising the supply, the POWER peripheral records the cause of
+//! the last reset, which persists across resets until it is cleared. As the
+//! chip also resets when waking from System OFF, the reset reason is how the
+//! program finds out that it woke up, and from what.
+//!
+//! Only some of the RAM sections may be kept powered in System OFF, see
+//! `Power::set_ram_retention`; the others lose their contents.
+use core::ops::Deref;
+
+use cortex_m::asm;
+
+use crate::gpio::{Input, Pin};
+use crate::gpiote::{self, Sense};
+use crate::target::{power, POWER};
+
+#[cfg(any(feature = "52832", feature = "52840"))]
+use crate::target::LPCOMP;
+
+/// Bits of the RESETREAS register
+mod resetreas {
+    pub const RESETPIN: u32 = 1 << 0;
+    pub const DOG: u32 = 1 << 1;
+    pub const SREQ: u32 = 1 << 2;
+    pub const LOCKUP: u32 = 1 << 3;
+    pub const OFF: u32 = 1 << 16;
+    pub const LPCOMP: u32 = 1 << 17;
+    pub const DIF: u32 = 1 << 18;
+    pub const NFC: u32 = 1 << 19;
+    pub const VBUS: u32 = 1 << 20;
+}
+
+/// Bits in INTENSET/INTENCLR
+const POFWARN_INT_BIT: u32 = 1 << 2;
+const SLEEPENTER_INT_BIT: u32 = 1 << 5;
+const SLEEPEXIT_INT_BIT: u32 = 1 << 6;
+
+/// An extension trait for constructing the high level interface
+pub trait PowerExt: Deref<Target = power::RegisterBlock> + Sized {
+    fn constrain(self) -> Power;
+}
+
+impl PowerExt for POWER {
+    fn constrain(self) -> Power {
+        Power { power: self }
+    }
+}
+
+/// A high level interface to the POWER peripheral
+pub struct Power {
+    power: POWER,
+}
+
+/// The causes of the last reset, as recorded in RESETREAS
+///
+/// Several causes may be recorded when the reset reason was not cleared in
+/// between resets. No cause at all means a power-on or brown-out reset.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ResetReason(u32);
+
+impl ResetReason {
+    /// The raw RESETREAS bits
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Power-on or brown-out reset, which clears all the other causes
+    pub fn is_power_on(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Reset from the reset pin
+    pub fn is_pin(self) -> bool {
+        self.0 & resetreas::RESETPIN != 0
+    }
+
+    /// Reset from the watchdog
+    pub fn is_watchdog(self) -> bool {
+        self.0 & resetreas::DOG != 0
+    }
+
+    /// Soft reset, requested through the AIRCR register
+    pub fn is_soft(self) -> bool {
+        self.0 & resetreas::SREQ != 0
+    }
+
+    /// Reset from a CPU lockup
+    pub fn is_lockup(self) -> bool {
+        self.0 & resetreas::LOCKUP != 0
+    }
+
+    /// Wake from System OFF by the DETECT signal of a GPIO pin
+    pub fn is_wake_gpio(self) -> bool {
+        self.0 & resetreas::OFF != 0
+    }
+
+    /// Wake from System OFF by the ANADETECT signal of the LPCOMP
+    pub fn is_wake_lpcomp(self) -> bool {
+        self.0 & resetreas::LPCOMP != 0
+    }
+
+    /// Wake from System OFF by the debug interface
+    pub fn is_wake_debug(self) -> bool {
+        self.0 & resetreas::DIF != 0
+    }
+
+    /// Wake from System OFF by the NFC field detector
+    pub fn is_wake_nfc(self) -> bool {
+        self.0 & resetreas::NFC != 0
+    }
+
+    /// Wake from System OFF by a voltage on VBUS
+    pub fn is_wake_vbus(self) -> bool {
+        self.0 & resetreas::VBUS != 0
+    }
+
+    /// Did the chip wake from System OFF, by any source?
+    pub fn is_wake_from_off(self) -> bool {
+        self.0
+            & (resetreas::OFF
+                | resetreas::LPCOMP
+                | resetreas::DIF
+                | resetreas::NFC
+                | resetreas::VBUS)
+            != 0
+    }
+}
+
+/// Supply voltage below which the POFWARN event is generated
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PofThreshold {
+    V17 = 4,
+    V18 = 5,
+    V19 = 6,
+    V20 = 7,
+    V21 = 8,
+    V22 = 9,
+    V23 = 10,
+    V24 = 11,
+    V25 = 12,
+    V26 = 13,
+    V27 = 14,
+    V28 = 15,
+}
+
+/// Trade-off between power consumption and wake-up latency in System ON
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SubPowerMode {
+    /// Resources are only kept running while in use, the reset default
+    LowPower,
+    /// Resources are kept running, for the shortest and constant latency
+    ConstantLatency,
+}
+
+/// Interrupts and events of the POWER peripheral
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PowerInterrupt {
+    /// The supply fell below the power-fail threshold
+    PofWarning,
+    /// The CPU entered sleep, on WFI or WFE
+    SleepEnter,
+    /// The CPU woke from sleep
+    SleepExit,
+}
+
+impl Power {
+    /// Return the raw interface to the POWER peripheral
+    pub fn free(self) -> POWER {
+        self.power
+    }
+
+    /// The causes of the last reset
+    pub fn reset_reason(&self) -> ResetReason {
+        ResetReason(self.power.resetreas.read().bits())
+    }
+
+    /// Clear the recorded reset causes
+    ///
+    /// They are otherwise kept across resets, see `ResetReason`.
+    pub fn clear_reset_reason(&mut self) {
+        // The register is cleared by writing `1` to the fields
+        let bits = self.power.resetreas.read().bits();
+        self.power.resetreas.write(|w| unsafe { w.bits(bits) });
+    }
+
+    /// Read and clear the causes of the last reset
+    pub fn take_reset_reason(&mut self) -> ResetReason {
+        let reason = self.reset_reason();
+        self.clear_reset_reason();
+        reason
+    }
+
+    /// Wake from System OFF when `pin` is at the `sense` level
+    ///
+    /// This is the same DETECT configuration as `gpiote::Port::sense`. The
+    /// pin must not be at its sensed level when entering System OFF, or the
+    /// chip wakes right away.
+    pub fn wake_on_pin<MODE>(&mut self, pin: &Pin<Input<MODE>>, sense: Sense) {
+        gpiote::set_sense(pin, sense);
+    }
+
+    /// Wake from System OFF when the analog `input` crosses `reference`
+    ///
+    /// The LPCOMP is configured and started, and keeps running in System
+    /// OFF. The reset reason of the wake is `is_wake_lpcomp`.
+    #[cfg(any(feature = "52832", feature = "52840"))]
+    pub fn wake_on_lpcomp(
+        &mut self,
+        lpcomp: &mut LPCOMP,
+        input: u8,
+        reference: LpcompReference,
+        detect: LpcompDetect,
+    ) -> Result<(), Error> {
+        if input > 7 {
+            return Err(Error::InvalidInput);
+        }
+        let refsel = reference.refsel()?;
+
+        lpcomp.enable.write(|w| unsafe { w.bits(0) });
+        lpcomp.psel.write(|w| unsafe { w.bits(u32::from(input)) });
+        lpcomp.refsel.write(|w| unsafe { w.bits(refsel) });
+        lpcomp.anadetect.write(|w| unsafe { w.bits(detect as u32) });
+        lpcomp.enable.write(|w| unsafe { w.bits(1) });
+        lpcomp.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        Ok(())
+    }
+
+    /// Enter System OFF, the deepest power saving mode
+    ///
+    /// The chip only leaves System OFF through a reset, by one of the
+    /// configured wake sources. When a debugger is attached System OFF is
+    /// only emulated, and the CPU waits here instead.
+    pub fn system_off(&mut self) -> ! {
+        self.power.systemoff.write(|w| unsafe { w.bits(1) });
+
+        loop {
+            asm::wfe();
+        }
+    }
+
+    /// Select the sub power mode used in System ON
+    pub fn set_sub_power_mode(&mut self, mode: SubPowerMode) {
+        match mode {
+            SubPowerMode::LowPower => self.power.tasks_lowpwr.write(|w| unsafe { w.bits(1) }),
+            SubPowerMode::ConstantLatency => {
+                self.power.tasks_constlat.write(|w| unsafe { w.bits(1) })
+            }
+        }
+    }
+
+    /// Generate the POFWARN event when the supply falls below `threshold`
+    pub fn enable_pof(&mut self, threshold: PofThreshold) {
+        self.power
+            .pofcon
+            .write(|w| unsafe { w.bits(1 | (threshold as u32) << 1) });
+    }
+
+    /// Disable the power-fail comparator
+    pub fn disable_pof(&mut self) {
+        self.power.pofcon.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Has the supply fallen below the power-fail threshold? Clears the
+    /// event
+    pub fn pof_warning(&mut self) -> bool {
+        let warning = self.power.events_pofwarn.read().bits() != 0;
+        if warning {
+            self.power.events_pofwarn.reset();
+        }
+        warning
+    }
+
+    /// Enable the POWER interrupt for an event
+    pub fn enable_interrupt(&mut self, int: PowerInterrupt) {
+        let bit = int_bit(int);
+        self.power.intenset.write(|w| unsafe { w.bits(bit) });
+    }
+
+    /// Disable the POWER interrupt for an event
+    pub fn disable_interrupt(&mut self, int: PowerInterrupt) {
+        let bit = int_bit(int);
+        self.power.intenclr.write(|w| unsafe { w.bits(bit) });
+    }
+
+    /// Use the DC/DC converter instead of the LDO regulator
+    ///
+    /// The converter needs an external LC filter, see the reference layout
+    /// of the board.
+    pub fn enable_dcdc(&mut self) {
+        self.power.dcdcen.write(|w| w.dcdcen().enabled());
+    }
+
+    /// Use the LDO regulator
+    pub fn disable_dcdc(&mut self) {
+        self.power.dcdcen.write(|w| w.dcdcen().disabled());
+    }
+
+    /// Use the DC/DC converter of the VDDH stage (REG0) instead of its LDO
+    #[cfg(feature = "52840")]
+    pub fn enable_dcdc0(&mut self) {
+        self.power.dcdcen0.write(|w| w.dcdcen().enabled());
+    }
+
+    /// Use the LDO regulator of the VDDH stage (REG0)
+    #[cfg(feature = "52840")]
+    pub fn disable_dcdc0(&mut self) {
+        self.power.dcdcen0.write(|w| w.dcdcen().disabled());
+    }
+
+    /// Number of RAM blocks
+    pub fn ram_blocks(&self) -> usize {
+        self.power.ram.len()
+    }
+
+    /// Number of sections of RAM block `block`
+    pub fn ram_sections(&self, block: usize) -> usize {
+        if cfg!(feature = "52840") && block == 8 {
+            6
+        } else {
+            2
+        }
+    }
+
+    /// Keep the `sections` of RAM block `block` powered in System ON
+    ///
+    /// `sections` is a bit mask, bit n for section n. Sections that are
+    /// switched off lose their contents.
+    pub fn set_ram_power(&mut self, block: usize, sections: u16, on: bool) -> Result<(), Error> {
+        let mask = self.ram_mask(block, sections)?;
+        let ram = &self.power.ram[block];
+        if on {
+            ram.powerset.write(|w| unsafe { w.bits(mask) });
+        } else {
+            ram.powerclr.write(|w| unsafe { w.bits(mask) });
+        }
+
+        Ok(())
+    }
+
+    /// Keep the contents of the `sections` of RAM block `block` in System
+    /// OFF
+    ///
+    /// `sections` is a bit mask, bit n for section n. Retention costs power,
+    /// so only the sections holding state to keep should be retained.
+    pub fn set_ram_retention(
+        &mut self,
+        block: usize,
+        sections: u16,
+        retain: bool,
+    ) -> Result<(), Error> {
+        let mask = self.ram_mask(block, sections)? << 16;
+        let ram = &self.power.ram[block];
+        if retain {
+            ram.powerset.write(|w| unsafe { w.bits(mask) });
+        } else {
+            ram.powerclr.write(|w| unsafe { w.bits(mask) });
+        }
+
+        Ok(())
+    }
+
+    /// Bit mask of the sections of RAM block `block` that are retained in
+    /// System OFF
+    pub fn ram_retention(&self, block: usize) -> Result<u16, Error> {
+        if block >= self.ram_blocks() {
+            return Err(Error::InvalidRamBlock);
+        }
+
+        Ok((self.power.ram[block].power.read().bits() >> 16) as u16)
+    }
+
+    fn ram_mask(&self, block: usize, sections: u16) -> Result<u32, Error> {
+        if block >= self.ram_blocks() {
+            return Err(Error::InvalidRamBlock);
+        }
+        if u32::from(sections) >> self.ram_sections(block) != 0 {
+            return Err(Error::InvalidRamSection);
+        }
+
+        Ok(u32::from(sections))
+    }
+}
+
+fn int_bit(int: PowerInterrupt) -> u32 {
+    match int {
+        PowerInterrupt::PofWarning => POFWARN_INT_BIT,
+        PowerInterrupt::SleepEnter => SLEEPENTER_INT_BIT,
+        PowerInterrupt::SleepExit => SLEEPEXIT_INT_BIT,
+    }
+}
+
+/// Reference voltage of the LPCOMP, a fraction of VDD
+#[cfg(any(feature = "52832", feature = "52840"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LpcompReference {
+    /// n/8 VDD, n from 1 to 7
+    Eighths(u8),
+    /// n/16 VDD, n odd from 1 to 15
+    Sixteenths(u8),
+}
+
+#[cfg(any(feature = "52832", feature = "52840"))]
+impl LpcompReference {
+    /// The REFSEL value
+    fn refsel(self) -> Result<u32, Error> {
+        match self {
+            LpcompReference::Eighths(n) if n >= 1 && n <= 7 => Ok(u32::from(n) - 1),
+            LpcompReference::Sixteenths(n) if n <= 15 && n % 2 == 1 => Ok(8 + u32::from(n) / 2),
+            _ => Err(Error::InvalidReference),
+        }
+    }
+}
+
+/// Crossing of the LPCOMP reference that wakes the chip
+#[cfg(any(feature = "52832", feature = "52840"))]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LpcompDetect {
+    /// Upward or downward crossing
+    Cross = 0,
+    /// Upward crossing
+    Up = 1,
+    /// Downward crossing
+    Down = 2,
+}
+
+/// Error types associated with the POWER peripheral
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The RAM block does not exist
+    InvalidRamBlock,
+    /// A section does not exist in the RAM block
+    InvalidRamSection,
+    /// The analog input does not exist
+    InvalidInput,
+    /// The LPCOMP reference can not be generated
+    InvalidReference,
+}
@@ -0,0 +1,356 @@
+//! HAL interface to the PPI peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 21
+//! - nrf52840: Section 6.14
+//!
+//! The Programmable Peripheral Interconnect connects an event of one
+//! peripheral to a task of another, without involving the CPU. The endpoints
+//! are the event and task registers of the peripherals, which the HAL
+//! wrappers hand out through methods such as `Timer::event_compare` or
+//! `Saadc::task_sample`. Only registers implementing `Event` can be used as
+//! an event endpoint, and only registers implementing `Task` as a task
+//! endpoint, so a wrongly wired channel is caught at compile time.
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use crate::target::{
+    gpiote, ppi, pwm0, rng, rtc0, saadc, temp, timer0, uarte0, PPI,
+};
+
+/// Number of programmable PPI channels
+pub const NUM_CHANNELS: usize = 20;
+
+/// Number of PPI channel groups
+pub const NUM_GROUPS: usize = 6;
+
+mod sealed {
+    pub trait Event {}
+    pub trait Task {}
+}
+
+/// An event register that can be used as the event endpoint of a channel
+pub trait Event: sealed::Event + Sized {
+    /// The address of the event register
+    fn event_address(&self) -> u32 {
+        self as *const Self as u32
+    }
+}
+
+/// A task register that can be used as the task endpoint of a channel
+pub trait Task: sealed::Task + Sized {
+    /// The address of the task register
+    fn task_address(&self) -> u32 {
+        self as *const Self as u32
+    }
+}
+
+macro_rules! impl_event {
+    ($($reg:ty,)*) => {
+        $(
+            impl sealed::Event for $reg {}
+            impl Event for $reg {}
+        )*
+    }
+}
+
+macro_rules! impl_task {
+    ($($reg:ty,)*) => {
+        $(
+            impl sealed::Task for $reg {}
+            impl Task for $reg {}
+        )*
+    }
+}
+
+impl_event!(
+    gpiote::EVENTS_IN,
+    gpiote::EVENTS_PORT,
+    pwm0::EVENTS_STOPPED,
+    pwm0::EVENTS_SEQSTARTED,
+    pwm0::EVENTS_SEQEND,
+    pwm0::EVENTS_PWMPERIODEND,
+    pwm0::EVENTS_LOOPSDONE,
+    rng::EVENTS_VALRDY,
+    rtc0::EVENTS_TICK,
+    rtc0::EVENTS_OVRFLW,
+    rtc0::EVENTS_COMPARE,
+    saadc::EVENTS_STARTED,
+    saadc::EVENTS_END,
+    saadc::EVENTS_DONE,
+    saadc::EVENTS_RESULTDONE,
+    saadc::EVENTS_CALIBRATEDONE,
+    saadc::EVENTS_STOPPED,
+    saadc::events_ch::LIMITH,
+    saadc::events_ch::LIMITL,
+    temp::EVENTS_DATARDY,
+    timer0::EVENTS_COMPARE,
+    uarte0::EVENTS_CTS,
+    uarte0::EVENTS_NCTS,
+    uarte0::EVENTS_RXDRDY,
+    uarte0::EVENTS_ENDRX,
+    uarte0::EVENTS_TXDRDY,
+    uarte0::EVENTS_ENDTX,
+    uarte0::EVENTS_ERROR,
+    uarte0::EVENTS_RXTO,
+    uarte0::EVENTS_RXSTARTED,
+    uarte0::EVENTS_TXSTARTED,
+    uarte0::EVENTS_TXSTOPPED,
+);
+
+impl_task!(
+    gpiote::TASKS_OUT,
+    ppi::tasks_chg::EN,
+    ppi::tasks_chg::DIS,
+    pwm0::TASKS_STOP,
+    pwm0::TASKS_SEQSTART,
+    pwm0::TASKS_NEXTSTEP,
+    rng::TASKS_START,
+    rng::TASKS_STOP,
+    rtc0::TASKS_START,
+    rtc0::TASKS_STOP,
+    rtc0::TASKS_CLEAR,
+    rtc0::TASKS_TRIGOVRFLW,
+    saadc::TASKS_START,
+    saadc::TASKS_SAMPLE,
+    saadc::TASKS_STOP,
+    saadc::TASKS_CALIBRATEOFFSET,
+    temp::TASKS_START,
+    temp::TASKS_STOP,
+    timer0::TASKS_START,
+    timer0::TASKS_STOP,
+    timer0::TASKS_COUNT,
+    timer0::TASKS_CLEAR,
+    timer0::TASKS_CAPTURE,
+    uarte0::TASKS_STARTRX,
+    uarte0::TASKS_STOPRX,
+    uarte0::TASKS_STARTTX,
+    uarte0::TASKS_STOPTX,
+    uarte0::TASKS_FLUSHRX,
+);
+
+#[cfg(not(feature = "52832"))]
+impl_task!(gpiote::TASKS_SET, gpiote::TASKS_CLR,);
+
+/// Extension trait to split the PPI peripheral in independent channels
+pub trait PpiExt: Deref<Target = ppi::RegisterBlock> + Sized {
+    /// Splits the PPI block into independent channels and groups
+    fn split(self) -> Parts;
+}
+
+fn regs() -> &'static ppi::RegisterBlock {
+    // NOTE(unsafe) each channel and group only touches its own registers,
+    // and the shared CHENSET/CHENCLR registers are write-one-to-set/clear
+    unsafe { &*PPI::ptr() }
+}
+
+macro_rules! ppi {
+    (
+        channels: [$($C:ident: ($ch:ident, $i:expr),)+],
+        groups: [$($G:ident: ($grp:ident, $j:expr),)+]
+    ) => {
+        $(
+            /// PPI channel (type state)
+            pub struct $C;
+
+            impl ChannelNum for $C {
+                const INDEX: usize = $i;
+            }
+        )+
+
+        $(
+            /// PPI channel group (type state)
+            pub struct $G;
+
+            impl GroupNum for $G {
+                const INDEX: usize = $j;
+            }
+        )+
+
+        /// PPI parts
+        pub struct Parts {
+            $(
+                pub $ch: Channel<$C>,
+            )+
+            $(
+                pub $grp: Group<$G>,
+            )+
+        }
+
+        impl PpiExt for PPI {
+            fn split(self) -> Parts {
+                // Start from a known state, nothing connected
+                self.chenclr.write(|w| unsafe { w.bits((1 << NUM_CHANNELS) - 1) });
+                for i in 0..NUM_GROUPS {
+                    self.chg[i].write(|w| unsafe { w.bits(0) });
+                }
+
+                Parts {
+                    $(
+                        $ch: Channel { _channel: PhantomData },
+                    )+
+                    $(
+                        $grp: Group { _group: PhantomData },
+                    )+
+                }
+            }
+        }
+    }
+}
+
+ppi!(
+    channels: [
+        C0: (ppi0, 0),
+        C1: (ppi1, 1),
+        C2: (ppi2, 2),
+        C3: (ppi3, 3),
+        C4: (ppi4, 4),
+        C5: (ppi5, 5),
+        C6: (ppi6, 6),
+        C7: (ppi7, 7),
+        C8: (ppi8, 8),
+        C9: (ppi9, 9),
+        C10: (ppi10, 10),
+        C11: (ppi11, 11),
+        C12: (ppi12, 12),
+        C13: (ppi13, 13),
+        C14: (ppi14, 14),
+        C15: (ppi15, 15),
+        C16: (ppi16, 16),
+        C17: (ppi17, 17),
+        C18: (ppi18, 18),
+        C19: (ppi19, 19),
+    ],
+    groups: [
+        G0: (group0, 0),
+        G1: (group1, 1),
+        G2: (group2, 2),
+        G3: (group3, 3),
+        G4: (group4, 4),
+        G5: (group5, 5),
+    ]
+);
+
+/// Channel number of a PPI channel (type state)
+pub trait ChannelNum {
+    const INDEX: usize;
+}
+
+/// Group number of a PPI channel group (type state)
+pub trait GroupNum {
+    const INDEX: usize;
+}
+
+/// A programmable PPI channel
+pub struct Channel<C> {
+    _channel: PhantomData<C>,
+}
+
+impl<C> Channel<C>
+where
+    C: ChannelNum,
+{
+    /// Set the event that triggers this channel
+    pub fn set_event_endpoint<E: Event>(&mut self, event: &E) {
+        regs().ch[C::INDEX]
+            .eep
+            .write(|w| unsafe { w.bits(event.event_address()) });
+    }
+
+    /// Set the task triggered by this channel
+    pub fn set_task_endpoint<T: Task>(&mut self, task: &T) {
+        regs().ch[C::INDEX]
+            .tep
+            .write(|w| unsafe { w.bits(task.task_address()) });
+    }
+
+    /// Set a second task triggered by this channel
+    pub fn set_fork_task_endpoint<T: Task>(&mut self, task: &T) {
+        regs().fork[C::INDEX]
+            .tep
+            .write(|w| unsafe { w.bits(task.task_address()) });
+    }
+
+    /// Remove the fork task of this channel
+    pub fn clear_fork_task_endpoint(&mut self) {
+        regs().fork[C::INDEX].tep.write(|w| unsafe { w.bits(0) });
+    }
+
+    /// Connect `event` to `task` and enable the channel
+    pub fn connect<E: Event, T: Task>(&mut self, event: &E, task: &T) {
+        self.set_event_endpoint(event);
+        self.set_task_endpoint(task);
+        self.enable();
+    }
+
+    /// Enable the channel
+    pub fn enable(&mut self) {
+        regs()
+            .chenset
+            .write(|w| unsafe { w.bits(1 << C::INDEX) });
+    }
+
+    /// Disable the channel
+    pub fn disable(&mut self) {
+        regs()
+            .chenclr
+            .write(|w| unsafe { w.bits(1 << C::INDEX) });
+    }
+
+    /// Is the channel enabled?
+    ///
+    /// This reflects the effect of channel groups as well.
+    pub fn is_enabled(&self) -> bool {
+        regs().chen.read().bits() & (1 << C::INDEX) != 0
+    }
+}
+
+/// A PPI channel group
+///
+/// Groups allow a set of channels to be enabled or disabled at once, either
+/// directly or from an event through the group's own tasks.
+pub struct Group<G> {
+    _group: PhantomData<G>,
+}
+
+impl<G> Group<G>
+where
+    G: GroupNum,
+{
+    /// Include `channel` in the group
+    pub fn add_channel<C: ChannelNum>(&mut self, _channel: &Channel<C>) {
+        regs().chg[G::INDEX]
+            .modify(|r, w| unsafe { w.bits(r.bits() | 1 << C::INDEX) });
+    }
+
+    /// Exclude `channel` from the group
+    pub fn remove_channel<C: ChannelNum>(&mut self, _channel: &Channel<C>) {
+        regs().chg[G::INDEX]
+            .modify(|r, w| unsafe { w.bits(r.bits() & !(1 << C::INDEX)) });
+    }
+
+    /// Enable all channels in the group
+    pub fn enable(&mut self) {
+        regs().tasks_chg[G::INDEX]
+            .en
+            .write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Disable all channels in the group
+    pub fn disable(&mut self) {
+        regs().tasks_chg[G::INDEX]
+            .dis
+            .write(|w| unsafe { w.bits(1) });
+    }
+
+    /// The task enabling the group, for use as a PPI task endpoint
+    pub fn task_enable(&self) -> &ppi::tasks_chg::EN {
+        &regs().tasks_chg[G::INDEX].en
+    }
+
+    /// The task disabling the group, for use as a PPI task endpoint
+    pub fn task_disable(&self) -> &ppi::tasks_chg::DIS {
+        &regs().tasks_chg[G::INDEX].dis
+    }
+}
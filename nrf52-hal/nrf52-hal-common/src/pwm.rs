@@ -0,0 +1,636 @@
+//! HAL interface to the PWM peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 45
+//! - nrf52840: Section 6.17
+//!
+//! The PWM peripheral reads its compare values from RAM through EasyDMA. The
+//! simple duty cycle interface (`set_duty` and the embedded-hal `Pwm` trait)
+//! plays back a one step sequence from a buffer inside the driver. Longer
+//! sequences are played back from `'static` buffers with `load_sequence` and
+//! `start_sequence`.
+use core::ops::Deref;
+use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
+
+use crate::gpio::{Output, Pin, PushPull};
+use crate::target::{pwm0, PWM0};
+use crate::target_constants::{SRAM_LOWER, SRAM_UPPER};
+use crate::time::Hertz;
+
+#[cfg(any(feature = "52832", feature = "52840"))]
+use crate::target::{PWM1, PWM2};
+
+#[cfg(feature = "52840")]
+use crate::target::PWM3;
+
+// Re-export SVD variants to allow user to directly set values
+pub use crate::target::pwm0::prescaler::PRESCALERW as Prescaler;
+
+/// Frequency of the PWM base clock, before the prescaler
+pub const PWM_CLK_FREQ: u32 = 16_000_000;
+
+/// Smallest value accepted by COUNTERTOP
+pub const MIN_COUNTER_TOP: u16 = 3;
+
+/// Largest value accepted by COUNTERTOP, and thus the largest duty cycle
+pub const MAX_COUNTER_TOP: u16 = 0x7FFF;
+
+/// Largest number of values in a sequence (SEQ[n].CNT is 15 bits)
+pub const MAX_SEQ_LEN: usize = 0x7FFF;
+
+// Bit 15 of a compare value selects the polarity of the first edge. With it
+// set the output starts high and falls when the counter reaches the compare
+// value, so the value equals the high time.
+const POLARITY_FALLING_EDGE: u16 = 1 << 15;
+
+pub trait PwmExt: Deref<Target = pwm0::RegisterBlock> + Sized {
+    fn constrain(
+        self,
+        pins: Pins,
+        prescaler: Prescaler,
+        max_duty: u16,
+    ) -> Result<Pwm<Self>, Error>;
+}
+
+macro_rules! impl_pwm_ext {
+    ($($pwm:ty,)*) => {
+        $(
+            impl PwmExt for $pwm {
+                fn constrain(self, pins: Pins, prescaler: Prescaler, max_duty: u16)
+                    -> Result<Pwm<Self>, Error>
+                {
+                    Pwm::new(self, pins, prescaler, max_duty)
+                }
+            }
+        )*
+    }
+}
+
+impl_pwm_ext!(PWM0,);
+
+#[cfg(any(feature = "52832", feature = "52840"))]
+impl_pwm_ext!(PWM1, PWM2,);
+
+#[cfg(feature = "52840")]
+impl_pwm_ext!(PWM3,);
+
+/// PWM output channels
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Channel {
+    C0,
+    C1,
+    C2,
+    C3,
+}
+
+impl Channel {
+    fn index(self) -> usize {
+        match self {
+            Channel::C0 => 0,
+            Channel::C1 => 1,
+            Channel::C2 => 2,
+            Channel::C3 => 3,
+        }
+    }
+}
+
+/// Counter mode of the wave counter
+pub enum CounterMode {
+    /// Count up to COUNTERTOP, giving edge-aligned PWM
+    Up,
+    /// Count up to COUNTERTOP and back down, giving center-aligned PWM
+    UpAndDown,
+}
+
+/// How a sequence buffer is distributed over the four channels
+pub enum LoadMode {
+    /// One value per step, used for all four channels
+    Common,
+    /// Two values per step, the first for channels 0 and 1, the second for
+    /// channels 2 and 3
+    Grouped,
+    /// Four values per step, one for each channel
+    Individual,
+    /// Four values per step, for channels 0 to 2 and the COUNTERTOP value
+    Waveform,
+}
+
+impl LoadMode {
+    fn values_per_step(&self) -> usize {
+        match self {
+            LoadMode::Common => 1,
+            LoadMode::Grouped => 2,
+            LoadMode::Individual | LoadMode::Waveform => 4,
+        }
+    }
+}
+
+/// When the sequence advances to its next step
+pub enum StepMode {
+    /// After every REFRESH + 1 PWM periods
+    Auto,
+    /// On every NEXTSTEP task
+    NextStep,
+}
+
+/// The two sequences held by the PWM peripheral
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Seq {
+    Seq0,
+    Seq1,
+}
+
+impl Seq {
+    fn index(self) -> usize {
+        match self {
+            Seq::Seq0 => 0,
+            Seq::Seq1 => 1,
+        }
+    }
+}
+
+/// Interrupts/Events that can be generated by the PWM peripheral
+pub enum PwmEvent {
+    Stopped,
+    SeqStarted0,
+    SeqStarted1,
+    SeqEnd0,
+    SeqEnd1,
+    PwmPeriodEnd,
+    LoopsDone,
+}
+
+impl PwmEvent {
+    fn bit(&self) -> u32 {
+        match self {
+            PwmEvent::Stopped => 1 << 1,
+            PwmEvent::SeqStarted0 => 1 << 2,
+            PwmEvent::SeqStarted1 => 1 << 3,
+            PwmEvent::SeqEnd0 => 1 << 4,
+            PwmEvent::SeqEnd1 => 1 << 5,
+            PwmEvent::PwmPeriodEnd => 1 << 6,
+            PwmEvent::LoopsDone => 1 << 7,
+        }
+    }
+}
+
+/// Interface to a PWM instance
+pub struct Pwm<T> {
+    periph: T,
+    // Compare values used by the duty cycle interface, read by EasyDMA
+    duty: [u16; 4],
+}
+
+impl<T> Pwm<T>
+where
+    T: PwmExt,
+{
+    /// Set up `pwm` with a COUNTERTOP of `max_duty`, which must be within
+    /// `MIN_COUNTER_TOP..=MAX_COUNTER_TOP`
+    pub fn new(
+        pwm: T,
+        pins: Pins,
+        prescaler: Prescaler,
+        max_duty: u16,
+    ) -> Result<Self, Error> {
+        check_counter_top(u32::from(max_duty))?;
+
+        // Select pins
+        for (psel, pin) in pwm
+            .psel
+            .out
+            .iter()
+            .zip([&pins.ch0, &pins.ch1, &pins.ch2, &pins.ch3].iter())
+        {
+            psel.write(|w| {
+                if let Some(pin) = pin {
+                    let w = unsafe { w.pin().bits(pin.pin) };
+                    #[cfg(feature = "52840")]
+                    let w = w.port().bit(pin.port);
+                    w.connect().connected()
+                } else {
+                    w.connect().disconnected()
+                }
+            });
+        }
+
+        // Enable PWM instance
+        pwm.enable.write(|w| w.enable().enabled());
+
+        pwm.mode.write(|w| w.updown().up());
+        pwm.prescaler.write(|w| w.prescaler().variant(prescaler));
+        pwm.countertop
+            .write(|w| unsafe { w.countertop().bits(max_duty) });
+        pwm.decoder
+            .write(|w| w.load().individual().mode().refresh_count());
+        pwm.loop_.write(|w| unsafe { w.cnt().bits(0) });
+        pwm.shorts.write(|w| unsafe { w.bits(0) });
+
+        Ok(Pwm {
+            periph: pwm,
+            duty: [POLARITY_FALLING_EDGE; 4],
+        })
+    }
+
+    /// Return the raw interface to the underlying PWM peripheral
+    pub fn free(self) -> T {
+        self.periph
+    }
+
+    /// Set the counter mode, changing the PWM period
+    pub fn set_counter_mode(&mut self, mode: CounterMode) {
+        self.periph.mode.write(|w| match mode {
+            CounterMode::Up => w.updown().up(),
+            CounterMode::UpAndDown => w.updown().up_and_down(),
+        });
+    }
+
+    /// Set the prescaler of the 16 MHz base clock
+    pub fn set_prescaler(&mut self, prescaler: Prescaler) {
+        self.periph
+            .prescaler
+            .write(|w| w.prescaler().variant(prescaler));
+    }
+
+    /// Set the COUNTERTOP value, which is also the duty cycle giving a
+    /// constant high output. Within `MIN_COUNTER_TOP..=MAX_COUNTER_TOP`.
+    pub fn set_max_duty(&mut self, max_duty: u16) -> Result<(), Error> {
+        check_counter_top(u32::from(max_duty))?;
+
+        self.periph
+            .countertop
+            .write(|w| unsafe { w.countertop().bits(max_duty) });
+
+        Ok(())
+    }
+
+    /// The current COUNTERTOP value
+    pub fn max_duty(&self) -> u16 {
+        self.periph.countertop.read().countertop().bits()
+    }
+
+    /// The duty cycle of `channel`, as the number of counter ticks the output
+    /// is high
+    pub fn duty(&self, channel: Channel) -> u16 {
+        self.duty[channel.index()] & !POLARITY_FALLING_EDGE
+    }
+
+    /// Set the duty cycle of `channel`, as the number of counter ticks the
+    /// output is high
+    ///
+    /// Any running sequence is replaced. Blocks until the new values have been
+    /// loaded, which takes at most one PWM period.
+    pub fn set_duty(&mut self, channel: Channel, duty: u16) {
+        let duty = duty.min(self.max_duty());
+        self.duty[channel.index()] = duty | POLARITY_FALLING_EDGE;
+
+        let pwm = &self.periph;
+
+        // Conservative compiler fence to prevent optimizations that do not
+        // take in to account actions by DMA. The fence has been placed here,
+        // before any DMA action has started
+        compiler_fence(SeqCst);
+
+        pwm.decoder
+            .write(|w| w.load().individual().mode().refresh_count());
+        pwm.seq[0]
+            .ptr
+            .write(|w| unsafe { w.bits(self.duty.as_ptr() as u32) });
+        pwm.seq[0].cnt.write(|w| unsafe { w.bits(4) });
+        pwm.seq[0].refresh.write(|w| unsafe { w.bits(0) });
+        pwm.seq[0].enddelay.write(|w| unsafe { w.bits(0) });
+        pwm.loop_.write(|w| unsafe { w.cnt().bits(0) });
+
+        pwm.events_seqend[0].reset();
+        pwm.tasks_seqstart[0].write(|w| unsafe { w.bits(1) });
+
+        // The values have been applied once SEQEND fires, after which the
+        // peripheral keeps outputting them and no longer reads the buffer
+        while pwm.events_seqend[0].read().bits() == 0 {}
+        pwm.events_seqend[0].reset();
+
+        // Conservative compiler fence to prevent optimizations that do not
+        // take in to account actions by DMA. The fence has been placed here,
+        // after all possible DMA actions have completed
+        compiler_fence(SeqCst);
+    }
+
+    /// Connect the pin of `channel` to the PWM output
+    pub fn enable_channel(&mut self, channel: Channel) {
+        self.periph.psel.out[channel.index()].modify(|_, w| w.connect().connected());
+    }
+
+    /// Disconnect the pin of `channel` from the PWM output
+    pub fn disable_channel(&mut self, channel: Channel) {
+        self.periph.psel.out[channel.index()].modify(|_, w| w.connect().disconnected());
+    }
+
+    /// The PWM frequency resulting from the prescaler, COUNTERTOP and
+    /// counter mode
+    pub fn frequency(&self) -> Hertz {
+        let div = 1 << self.periph.prescaler.read().prescaler().bits();
+        let mut top = u32::from(self.max_duty());
+        if self.periph.mode.read().updown().is_up_and_down() {
+            top *= 2;
+        }
+
+        Hertz(PWM_CLK_FREQ / div / top)
+    }
+
+    /// Choose the prescaler and COUNTERTOP to get as close as possible to
+    /// `freq`, keeping the largest possible duty cycle resolution
+    pub fn set_period(&mut self, freq: Hertz) -> Result<(), Error> {
+        let mut freq = freq.0;
+        if self.periph.mode.read().updown().is_up_and_down() {
+            freq *= 2;
+        }
+        if freq == 0 {
+            return Err(Error::PeriodOutOfRange);
+        }
+
+        for div in 0..8 {
+            let top = (PWM_CLK_FREQ >> div) / freq;
+            if top <= u32::from(MAX_COUNTER_TOP) {
+                if check_counter_top(top).is_err() {
+                    return Err(Error::PeriodOutOfRange);
+                }
+                self.periph
+                    .prescaler
+                    .write(|w| unsafe { w.prescaler().bits(div as u8) });
+                return self.set_max_duty(top as u16);
+            }
+        }
+
+        Err(Error::PeriodOutOfRange)
+    }
+
+    /// Load a sequence for playback
+    ///
+    /// `buffer` holds the compare values, distributed over the channels as
+    /// selected with `set_load_mode`. Bit 15 of every value selects the
+    /// polarity, see the product specification. Each value is played for
+    /// `refresh + 1` PWM periods (in `StepMode::Auto`), and `end_delay`
+    /// periods are added after the last value.
+    ///
+    /// The buffer must be located in data RAM, as EasyDMA can not read from
+    /// flash.
+    pub fn load_sequence(
+        &mut self,
+        seq: Seq,
+        buffer: &'static [u16],
+        refresh: u32,
+        end_delay: u32,
+    ) -> Result<(), Error> {
+        let p = buffer.as_ptr() as usize;
+        if p < SRAM_LOWER || p >= SRAM_UPPER {
+            return Err(Error::DMABufferNotInDataMemory);
+        }
+        if buffer.len() > MAX_SEQ_LEN {
+            return Err(Error::SequenceTooLong);
+        }
+        if refresh >= (1 << 24) || end_delay >= (1 << 24) {
+            return Err(Error::RefreshOutOfRange);
+        }
+
+        let i = seq.index();
+        self.periph.seq[i].ptr.write(|w| unsafe { w.bits(p as u32) });
+        self.periph.seq[i]
+            .cnt
+            .write(|w| unsafe { w.bits(buffer.len() as u32) });
+        self.periph.seq[i]
+            .refresh
+            .write(|w| unsafe { w.bits(refresh) });
+        self.periph.seq[i]
+            .enddelay
+            .write(|w| unsafe { w.bits(end_delay) });
+
+        Ok(())
+    }
+
+    /// Select how sequence values are distributed over the channels, and
+    /// what advances the sequence
+    ///
+    /// The length of sequences loaded afterwards must be a multiple of the
+    /// number of values per step of `load`, which is checked by
+    /// `start_sequence`.
+    pub fn set_load_mode(&mut self, load: LoadMode, step: StepMode) {
+        self.periph.decoder.write(|w| {
+            let w = match load {
+                LoadMode::Common => w.load().common(),
+                LoadMode::Grouped => w.load().grouped(),
+                LoadMode::Individual => w.load().individual(),
+                LoadMode::Waveform => w.load().wave_form(),
+            };
+            match step {
+                StepMode::Auto => w.mode().refresh_count(),
+                StepMode::NextStep => w.mode().next_step(),
+            }
+        });
+    }
+
+    /// Play sequence 0 followed by sequence 1, `count` times. 15 bits of
+    /// range, `0` disables looping so only the started sequence is played.
+    pub fn set_loop(&mut self, count: u16) -> Result<(), Error> {
+        if count > 0x7FFF {
+            return Err(Error::LoopCountOutOfRange);
+        }
+
+        self.periph.loop_.write(|w| unsafe { w.cnt().bits(count) });
+
+        Ok(())
+    }
+
+    /// Restart the loop from sequence 0 when LOOPSDONE fires, playing the
+    /// sequences continuously until stopped
+    pub fn set_continuous(&mut self, continuous: bool) {
+        self.periph
+            .shorts
+            .modify(|_, w| w.loopsdone_seqstart0().bit(continuous));
+    }
+
+    /// Stop the PWM once LOOPSDONE fires
+    pub fn set_stop_when_done(&mut self, stop: bool) {
+        self.periph
+            .shorts
+            .modify(|_, w| w.loopsdone_stop().bit(stop));
+    }
+
+    /// Start playback of a loaded sequence
+    pub fn start_sequence(&mut self, seq: Seq) -> Result<(), Error> {
+        let len = self.periph.seq[seq.index()].cnt.read().bits() as usize;
+        let per_step = match self.periph.decoder.read().load().bits() {
+            0 => 1,
+            1 => 2,
+            _ => 4,
+        };
+        if len == 0 || len % per_step != 0 {
+            return Err(Error::SequenceLength);
+        }
+
+        // Conservative compiler fence to prevent optimizations that do not
+        // take in to account actions by DMA. The fence has been placed here,
+        // before any DMA action has started
+        compiler_fence(SeqCst);
+
+        self.periph.tasks_seqstart[seq.index()].write(|w| unsafe { w.bits(1) });
+
+        Ok(())
+    }
+
+    /// Advance to the next step of a sequence in `StepMode::NextStep`
+    pub fn next_step(&mut self) {
+        self.periph.tasks_nextstep.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Stop the PWM generation at the end of the current PWM period
+    pub fn stop(&mut self) {
+        self.periph.tasks_stop.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Enable the generation of a hardware interrupt from a given event
+    pub fn enable_interrupt(&mut self, evt: PwmEvent) {
+        self.periph.intenset.write(|w| unsafe { w.bits(evt.bit()) });
+    }
+
+    /// Disable the generation of a hardware interrupt from a given event
+    pub fn disable_interrupt(&mut self, evt: PwmEvent) {
+        self.periph.intenclr.write(|w| unsafe { w.bits(evt.bit()) });
+    }
+
+    /// Obtain the state of a given event, and optionally clear the event
+    /// if it is set
+    pub fn get_event_triggered(&mut self, evt: PwmEvent, clear_on_read: bool) -> bool {
+        let pwm = &self.periph;
+        let triggered = match evt {
+            PwmEvent::Stopped => pwm.events_stopped.read().bits() != 0,
+            PwmEvent::SeqStarted0 => pwm.events_seqstarted[0].read().bits() != 0,
+            PwmEvent::SeqStarted1 => pwm.events_seqstarted[1].read().bits() != 0,
+            PwmEvent::SeqEnd0 => pwm.events_seqend[0].read().bits() != 0,
+            PwmEvent::SeqEnd1 => pwm.events_seqend[1].read().bits() != 0,
+            PwmEvent::PwmPeriodEnd => pwm.events_pwmperiodend.read().bits() != 0,
+            PwmEvent::LoopsDone => pwm.events_loopsdone.read().bits() != 0,
+        };
+
+        if triggered && clear_on_read {
+            match evt {
+                PwmEvent::Stopped => pwm.events_stopped.reset(),
+                PwmEvent::SeqStarted0 => pwm.events_seqstarted[0].reset(),
+                PwmEvent::SeqStarted1 => pwm.events_seqstarted[1].reset(),
+                PwmEvent::SeqEnd0 => pwm.events_seqend[0].reset(),
+                PwmEvent::SeqEnd1 => pwm.events_seqend[1].reset(),
+                PwmEvent::PwmPeriodEnd => pwm.events_pwmperiodend.reset(),
+                PwmEvent::LoopsDone => pwm.events_loopsdone.reset(),
+            }
+        }
+
+        triggered
+    }
+
+    /// The SEQEND event of a sequence, for use as a PPI event endpoint
+    pub fn event_seqend(&self, seq: Seq) -> &pwm0::EVENTS_SEQEND {
+        &self.periph.events_seqend[seq.index()]
+    }
+
+    /// The LOOPSDONE event, for use as a PPI event endpoint
+    pub fn event_loopsdone(&self) -> &pwm0::EVENTS_LOOPSDONE {
+        &self.periph.events_loopsdone
+    }
+
+    /// The PWMPERIODEND event, for use as a PPI event endpoint
+    pub fn event_pwmperiodend(&self) -> &pwm0::EVENTS_PWMPERIODEND {
+        &self.periph.events_pwmperiodend
+    }
+
+    /// The STOPPED event, for use as a PPI event endpoint
+    pub fn event_stopped(&self) -> &pwm0::EVENTS_STOPPED {
+        &self.periph.events_stopped
+    }
+
+    /// The SEQSTART task of a sequence, for use as a PPI task endpoint
+    pub fn task_seqstart(&self, seq: Seq) -> &pwm0::TASKS_SEQSTART {
+        &self.periph.tasks_seqstart[seq.index()]
+    }
+
+    /// The NEXTSTEP task, for use as a PPI task endpoint
+    pub fn task_nextstep(&self) -> &pwm0::TASKS_NEXTSTEP {
+        &self.periph.tasks_nextstep
+    }
+
+    /// The STOP task, for use as a PPI task endpoint
+    pub fn task_stop(&self) -> &pwm0::TASKS_STOP {
+        &self.periph.tasks_stop
+    }
+}
+
+impl<T> embedded_hal::Pwm for Pwm<T>
+where
+    T: PwmExt,
+{
+    type Channel = Channel;
+    type Time = Hertz;
+    type Duty = u16;
+
+    fn disable(&mut self, channel: Channel) {
+        self.disable_channel(channel)
+    }
+
+    fn enable(&mut self, channel: Channel) {
+        self.enable_channel(channel)
+    }
+
+    fn get_period(&self) -> Hertz {
+        self.frequency()
+    }
+
+    fn get_duty(&self, channel: Channel) -> u16 {
+        self.duty(channel)
+    }
+
+    fn get_max_duty(&self) -> u16 {
+        self.max_duty()
+    }
+
+    fn set_duty(&mut self, channel: Channel, duty: u16) {
+        Pwm::set_duty(self, channel, duty)
+    }
+
+    /// Set the PWM frequency, see `Pwm::set_period`
+    ///
+    /// Frequencies out of range are ignored.
+    fn set_period<P>(&mut self, period: P)
+    where
+        P: Into<Hertz>,
+    {
+        let _ = Pwm::set_period(self, period.into());
+    }
+}
+
+/// Checks that `top` is accepted by COUNTERTOP
+fn check_counter_top(top: u32) -> Result<(), Error> {
+    if top < u32::from(MIN_COUNTER_TOP) || top > u32::from(MAX_COUNTER_TOP) {
+        return Err(Error::CounterTopOutOfRange);
+    }
+    Ok(())
+}
+
+/// GPIO pins for the PWM outputs, `None` if unused
+pub struct Pins {
+    pub ch0: Option<Pin<Output<PushPull>>>,
+    pub ch1: Option<Pin<Output<PushPull>>>,
+    pub ch2: Option<Pin<Output<PushPull>>>,
+    pub ch3: Option<Pin<Output<PushPull>>>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// EasyDMA can only read from data memory, read only buffers in flash will fail
+    DMABufferNotInDataMemory,
+    SequenceTooLong,
+    /// The sequence length is zero or not a multiple of the values per step
+    SequenceLength,
+    RefreshOutOfRange,
+    LoopCountOutOfRange,
+    CounterTopOutOfRange,
+    PeriodOutOfRange,
+}
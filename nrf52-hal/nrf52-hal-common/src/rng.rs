@@ -1,9 +1,20 @@
 //! HAL interface to the RNG peripheral
 //!
 //! See nRF52832 product specification, chapter 26.
+//!
+//! The RNG produces a byte of true randomness every few tens of
+//! microseconds, with the bias correction (DERCEN) enabled by `constrain`.
+//! Bias correction makes the output uniform, at the cost of a lower and
+//! varying rate, and is what the `CryptoRng` implementations rely on. It is
+//! therefore always on.
+//!
+//! For random data on demand, `BufferedRng` keeps a buffer of random bytes
+//! filled from the RNG interrupt. For high rates, the hardware is best used
+//! to seed a software CSPRNG, see `Rng::seed_rng`.
 
 use core::ops::Deref;
-use rand_core::{CryptoRng, RngCore};
+use heapless::{spsc::Queue, ArrayLength};
+use rand_core::{CryptoRng, RngCore, SeedableRng};
 
 use crate::target::{rng, RNG};
 
@@ -20,10 +31,16 @@ impl RngExt for RNG {
 
 /// Interface to the RNG peripheral
 ///
-/// Right now, this is very basic, only providing blocking interfaces.
+/// Provides blocking interfaces, see `BufferedRng` for an interrupt driven
+/// one.
 pub struct Rng(RNG);
 
 impl Rng {
+    /// Return the raw interface to the RNG peripheral
+    pub fn free(self) -> RNG {
+        self.0
+    }
+
     /// Fill the provided buffer with random bytes
     ///
     /// Will block until the buffer is full.
@@ -31,16 +48,31 @@ impl Rng {
         self.0.tasks_start.write(|w| unsafe { w.bits(1) });
 
         for b in buf {
-            // Wait for random byte to become ready, reset the flag once it is
-            while self.0.events_valrdy.read().bits() == 0 {}
-            self.0.events_valrdy.write(|w| unsafe { w.bits(0) });
-
-            *b = self.0.value.read().value().bits();
+            *b = self.wait_value();
         }
 
         self.0.tasks_stop.write(|w| unsafe { w.bits(1) });
     }
 
+    /// Seed a software random number generator from the RNG
+    ///
+    /// A cryptographically secure generator, such as ChaCha, seeded this way
+    /// provides random data at a much higher rate than the RNG.
+    pub fn seed_rng<R: SeedableRng>(&mut self) -> R {
+        let mut seed = R::Seed::default();
+        self.random(seed.as_mut());
+        R::from_seed(seed)
+    }
+
+    /// Wait for a random byte of the started RNG
+    fn wait_value(&mut self) -> u8 {
+        // Wait for random byte to become ready, reset the flag once it is
+        while self.0.events_valrdy.read().bits() == 0 {}
+        self.0.events_valrdy.write(|w| unsafe { w.bits(0) });
+
+        self.0.value.read().value().bits()
+    }
+
     /// Return a random `u8`
     pub fn random_u8(&mut self) -> u8 {
         let mut buf = [0; 1];
@@ -102,3 +134,128 @@ impl RngCore for Rng {
 }
 
 impl CryptoRng for Rng {}
+
+/// An RNG filling a buffer of random bytes from its interrupt
+///
+/// The RNG runs while the buffer is not full, so random data is usually
+/// available right away. `process_interrupt` must be called from the RNG
+/// interrupt.
+pub struct BufferedRng<N>
+where
+    N: ArrayLength<u8>,
+{
+    rng: Rng,
+    buffer: Queue<u8, N>,
+}
+
+impl<N> BufferedRng<N>
+where
+    N: ArrayLength<u8>,
+{
+    /// Start filling `buffer` from `rng`
+    pub fn new(rng: Rng, buffer: Queue<u8, N>) -> Self {
+        let mut buffered = BufferedRng { rng, buffer };
+
+        buffered.rng.0.events_valrdy.reset();
+        buffered.rng.0.intenset.write(|w| w.valrdy().set());
+        buffered.start();
+
+        buffered
+    }
+
+    /// Stop the RNG, returning it and the buffer
+    pub fn free(self) -> (Rng, Queue<u8, N>) {
+        self.rng.0.intenclr.write(|w| w.valrdy().clear());
+        self.rng.0.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.rng.0.events_valrdy.reset();
+
+        (self.rng, self.buffer)
+    }
+
+    /// Number of random bytes in the buffer
+    pub fn available(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Handle the RNG interrupt, moving the new random byte to the buffer
+    pub fn process_interrupt(&mut self) {
+        if self.rng.0.events_valrdy.read().bits() == 0 {
+            return;
+        }
+        self.rng.0.events_valrdy.reset();
+
+        let value = self.rng.0.value.read().value().bits();
+        if self.buffer.enqueue(value).is_err() {
+            // Full, the RNG is started again once bytes are taken
+            self.rng.0.tasks_stop.write(|w| unsafe { w.bits(1) });
+        }
+    }
+
+    /// Fill the provided buffer with random bytes
+    ///
+    /// The buffered bytes are used first. When there are not enough of them
+    /// the rest is generated, blocking until the buffer is full.
+    pub fn random(&mut self, buf: &mut [u8]) {
+        let mut n = 0;
+        while n < buf.len() {
+            match self.buffer.dequeue() {
+                Some(b) => buf[n] = b,
+                None => break,
+            }
+            n += 1;
+        }
+
+        if n == buf.len() {
+            return;
+        }
+
+        // Read the RNG directly, with its interrupt disabled so that
+        // `process_interrupt` can not take the bytes from under us, should it
+        // be called without the lock that an RTFM resource would need
+        self.rng.0.intenclr.write(|w| w.valrdy().clear());
+        self.start();
+        for b in &mut buf[n..] {
+            *b = self.rng.wait_value();
+        }
+        self.rng.0.intenset.write(|w| w.valrdy().set());
+    }
+
+    /// Seed a software random number generator, see `Rng::seed_rng`
+    pub fn seed_rng<R: SeedableRng>(&mut self) -> R {
+        let mut seed = R::Seed::default();
+        self.random(seed.as_mut());
+        R::from_seed(seed)
+    }
+
+    fn start(&mut self) {
+        self.rng.0.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+}
+
+impl<N> RngCore for BufferedRng<N>
+where
+    N: ArrayLength<u8>,
+{
+    fn next_u32(&mut self) -> u32 {
+        let mut buf = [0; 4];
+        self.random(&mut buf);
+        u32::from_le_bytes(buf)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut buf = [0; 8];
+        self.random(&mut buf);
+        u64::from_le_bytes(buf)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.random(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.random(dest);
+        Ok(())
+    }
+}
+
+impl<N> CryptoRng for BufferedRng<N> where N: ArrayLength<u8> {}
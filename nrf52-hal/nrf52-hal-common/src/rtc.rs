@@ -2,7 +2,9 @@
 
 use core::ops::Deref;
 
+use crate::clocks::LFCLK_FREQ;
 use crate::target::{rtc0, RTC0, RTC1};
+use crate::time::{Hertz, MicroSeconds};
 
 #[cfg(not(feature = "52810"))]
 use crate::target::RTC2;
@@ -14,15 +16,33 @@ pub struct Stopped;
 /// The RTC has been started
 pub struct Started;
 
+/// Width of the counter in bits
+const COUNTER_BITS: u32 = 24;
+
+/// Mask of the counter and compare registers
+const COUNTER_MASK: u32 = (1 << COUNTER_BITS) - 1;
+
+/// Largest prescaler value, 12 bits of range
+const MAX_PRESCALER: u32 = (1 << 12) - 1;
+
+/// Compare values closer to the counter than this may not trigger the
+/// COMPARE event, see the product specification
+pub const MIN_COMPARE_TICKS: u32 = 2;
+
 /// An opaque high level interface to an RTC peripheral
 pub struct Rtc<T, M> {
     periph: T,
     _mode: M,
+    /// Number of counter overflows seen
+    overflows: u64,
 }
 
 /// An extension trait for constructing the high level interface
 pub trait RtcExt: Deref<Target = rtc0::RegisterBlock> + Sized {
     fn constrain(self) -> Rtc<Self, Stopped>;
+
+    /// Configure the RTC, starting from the current prescaler
+    fn builder(self) -> RtcBuilder<Self>;
 }
 
 macro_rules! impl_rtc_ext {
@@ -33,8 +53,13 @@ macro_rules! impl_rtc_ext {
                     Rtc {
                         periph: self,
                         _mode: Stopped,
+                        overflows: 0,
                     }
                 }
+
+                fn builder(self) -> RtcBuilder<Self> {
+                    RtcBuilder::new(self)
+                }
             }
         )*
     }
@@ -75,6 +100,7 @@ where
         Rtc {
             periph: self.periph,
             _mode: Started,
+            overflows: self.overflows,
         }
     }
 
@@ -86,6 +112,7 @@ where
         Rtc {
             periph: self.periph,
             _mode: Stopped,
+            overflows: self.overflows,
         }
     }
 
@@ -236,6 +263,8 @@ where
 
     /// Set the compare value of a given register. The compare registers have a width
     /// of 24 bits
+    ///
+    /// A duration is converted to a number of ticks with `ticks`.
     pub fn set_compare(
         &mut self,
         reg: RtcCompareReg,
@@ -264,6 +293,143 @@ where
         self.periph.counter.read().bits()
     }
 
+    /// The prescaler, the counter frequency being 32768 Hz / (`prescaler` + 1)
+    pub fn prescaler(&self) -> u32 {
+        self.periph.prescaler.read().bits()
+    }
+
+    /// The counter frequency, as set by the prescaler, rounded down to whole
+    /// Hertz
+    ///
+    /// Use `ticks` and `duration` to convert between ticks and time, they
+    /// are exact for any prescaler.
+    pub fn frequency(&self) -> Hertz {
+        Hertz(LFCLK_FREQ / (self.prescaler() + 1))
+    }
+
+    /// Number of ticks from the current counter value until the counter
+    /// reaches `target`, taking the 24-bit wrap into account
+    pub fn ticks_until(&self, target: u32) -> u32 {
+        wrapping_ticks(self.get_counter(), target)
+    }
+
+    /// Set a compare register to fire `duration` from now
+    ///
+    /// The compare value wraps with the counter. Durations shorter than
+    /// `MIN_COMPARE_TICKS` are extended to it, as the event could be missed
+    /// otherwise. Returns the compare value.
+    pub fn set_compare_in<D: Into<MicroSeconds>>(
+        &mut self,
+        reg: RtcCompareReg,
+        duration: D,
+    ) -> Result<u32, Error> {
+        let ticks = self.ticks(duration)?.max(MIN_COMPARE_TICKS);
+        let val = self.get_counter().wrapping_add(ticks) & COUNTER_MASK;
+        self.set_compare(reg, val)?;
+
+        Ok(val)
+    }
+
+    /// Reset the counter and the overflow count to zero
+    pub fn clear_counter(&mut self) {
+        unsafe {
+            self.periph.tasks_clear.write(|w| w.bits(1));
+        }
+        self.periph.events_ovrflw.reset();
+        self.overflows = 0;
+    }
+
+    /// Ticks since the counter was last cleared, extended to 64 bits
+    ///
+    /// Counter overflows are counted from the OVRFLW event, which must be
+    /// enabled as an event or interrupt, and which this method consumes. The
+    /// method must be called at least once per overflow period, typically
+    /// from the overflow interrupt through `process_overflow`.
+    pub fn uptime_ticks(&mut self) -> u64 {
+        self.process_overflow();
+        let mut counter = self.get_counter();
+
+        // The counter may have wrapped after the overflow was checked, in
+        // which case it must be read again to match the overflow count
+        if self.process_overflow() {
+            counter = self.get_counter();
+        }
+
+        self.overflows << COUNTER_BITS | u64::from(counter)
+    }
+
+    /// Count a pending counter overflow, returning whether there was one
+    pub fn process_overflow(&mut self) -> bool {
+        let overflow = self.periph.events_ovrflw.read().bits() != 0;
+        if overflow {
+            self.periph.events_ovrflw.reset();
+            self.overflows += 1;
+        }
+        overflow
+    }
+
+    /// Number of counter ticks in `duration`, rounded down
+    ///
+    /// Fails if the result does not fit the 24 bits of the compare registers,
+    /// so it can be used to compute compare values.
+    pub fn ticks<D: Into<MicroSeconds>>(&self, duration: D) -> Result<u32, Error> {
+        ticks_in(self.prescaler(), duration.into()).ok_or(Error::CompareOutOfRange)
+    }
+
+    /// The time taken by `ticks` counter ticks, rounded down to whole
+    /// microseconds, saturating
+    pub fn duration(&self, ticks: u32) -> MicroSeconds {
+        duration_of(self.prescaler(), ticks)
+    }
+
+    /// The TICK event, for use as a PPI event endpoint
+    ///
+    /// The event must also be enabled with `enable_event` to be routed.
+    pub fn event_tick(&self) -> &rtc0::EVENTS_TICK {
+        &self.periph.events_tick
+    }
+
+    /// The OVRFLW event, for use as a PPI event endpoint
+    ///
+    /// The event must also be enabled with `enable_event` to be routed.
+    pub fn event_overflow(&self) -> &rtc0::EVENTS_OVRFLW {
+        &self.periph.events_ovrflw
+    }
+
+    /// The COMPARE event of a given register, for use as a PPI event endpoint
+    ///
+    /// The event must also be enabled with `enable_event` to be routed.
+    pub fn event_compare(&self, reg: RtcCompareReg) -> &rtc0::EVENTS_COMPARE {
+        let reg = match reg {
+            RtcCompareReg::Compare0 => 0,
+            RtcCompareReg::Compare1 => 1,
+            RtcCompareReg::Compare2 => 2,
+            RtcCompareReg::Compare3 => 3,
+        };
+
+        &self.periph.events_compare[reg]
+    }
+
+    /// The START task, for use as a PPI task endpoint
+    pub fn task_start(&self) -> &rtc0::TASKS_START {
+        &self.periph.tasks_start
+    }
+
+    /// The STOP task, for use as a PPI task endpoint
+    pub fn task_stop(&self) -> &rtc0::TASKS_STOP {
+        &self.periph.tasks_stop
+    }
+
+    /// The CLEAR task, for use as a PPI task endpoint
+    pub fn task_clear(&self) -> &rtc0::TASKS_CLEAR {
+        &self.periph.tasks_clear
+    }
+
+    /// The TRIGOVRFLW task, for use as a PPI task endpoint
+    pub fn task_trigger_overflow(&self) -> &rtc0::TASKS_TRIGOVRFLW {
+        &self.periph.tasks_trigovrflw
+    }
+
     /// Destructure the high level interface. Does not reset any configuration made
     /// to the given RTC peripheral
     pub fn release(self) -> T {
@@ -278,6 +444,48 @@ pub enum Error {
     CompareOutOfRange,
 }
 
+/// Ticks from `from` to `to` on the 24-bit counter, modulo the wrap
+fn wrapping_ticks(from: u32, to: u32) -> u32 {
+    to.wrapping_sub(from) & COUNTER_MASK
+}
+
+/// Ticks in `duration` at a prescaler, rounded down, `None` beyond 24 bits
+///
+/// Computed from the low frequency clock, as the counter frequency is not a
+/// whole number of Hertz for most prescalers.
+fn ticks_in(prescaler: u32, duration: MicroSeconds) -> Option<u32> {
+    let ticks =
+        u64::from(duration.0) * u64::from(LFCLK_FREQ) / (1_000_000 * u64::from(prescaler + 1));
+    if ticks > u64::from(COUNTER_MASK) {
+        None
+    } else {
+        Some(ticks as u32)
+    }
+}
+
+/// The duration of `ticks` at a prescaler, rounded down, saturating
+fn duration_of(prescaler: u32, ticks: u32) -> MicroSeconds {
+    let us = u64::from(ticks) * u64::from(prescaler + 1) * 1_000_000 / u64::from(LFCLK_FREQ);
+    MicroSeconds(us.min(u64::from(u32::max_value())) as u32)
+}
+
+/// The prescaler for the counter frequency closest to `frequency`
+///
+/// Frequencies range from 8 Hz to 32768 Hz, others are rejected.
+pub fn prescaler_for<F: Into<Hertz>>(frequency: F) -> Result<u32, Error> {
+    let frequency = frequency.into().0;
+    if frequency == 0 || frequency > LFCLK_FREQ {
+        return Err(Error::PrescalerOutOfRange);
+    }
+
+    let divider = (LFCLK_FREQ + frequency / 2) / frequency;
+    if divider == 0 || divider - 1 > MAX_PRESCALER {
+        return Err(Error::PrescalerOutOfRange);
+    }
+
+    Ok(divider - 1)
+}
+
 impl<T> Rtc<T, Stopped>
 where
     T: RtcExt,
@@ -285,7 +493,7 @@ where
     /// Set the prescaler for the RTC peripheral. 12 bits of range.
     /// fRTC = 32_768 / (`prescaler` + 1 )
     pub fn set_prescaler(&mut self, prescaler: u32) -> Result<(), Error> {
-        if prescaler >= (1 << 12) {
+        if prescaler > MAX_PRESCALER {
             return Err(Error::PrescalerOutOfRange);
         }
 
@@ -294,3 +502,119 @@ where
         Ok(())
     }
 }
+
+/// RTC configuration, obtained with `RtcExt::builder`
+pub struct RtcBuilder<T> {
+    rtc: T,
+    prescaler: Result<u32, Error>,
+}
+
+impl<T> RtcBuilder<T>
+where
+    T: RtcExt,
+{
+    fn new(rtc: T) -> Self {
+        let prescaler = Ok(rtc.prescaler.read().bits());
+        RtcBuilder { rtc, prescaler }
+    }
+
+    /// Count at the frequency closest to `frequency`, see `prescaler_for`
+    ///
+    /// The frequency obtained is exactly 32768 Hz / (prescaler + 1), which
+    /// `Rtc::ticks` and `Rtc::duration` account for.
+    pub fn frequency<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        self.prescaler = prescaler_for(frequency);
+        self
+    }
+
+    /// Divide the 32768 Hz clock by `prescaler` + 1, 12 bits of range
+    pub fn prescaler(mut self, prescaler: u32) -> Self {
+        self.prescaler = if prescaler > MAX_PRESCALER {
+            Err(Error::PrescalerOutOfRange)
+        } else {
+            Ok(prescaler)
+        };
+        self
+    }
+
+    /// Create the stopped RTC, failing if the frequency or prescaler is out
+    /// of range
+    pub fn build(self) -> Result<Rtc<T, Stopped>, Error> {
+        let prescaler = self.prescaler?;
+        let mut rtc = self.rtc.constrain();
+        rtc.set_prescaler(prescaler)?;
+
+        Ok(rtc)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prescaler_range() {
+        assert_eq!(prescaler_for(Hertz(32_768)), Ok(0));
+        assert_eq!(prescaler_for(Hertz(16_384)), Ok(1));
+        assert_eq!(prescaler_for(Hertz(8)), Ok(MAX_PRESCALER));
+
+        for &frequency in &[0, 7, 32_769, 65_535, 100_000] {
+            let prescaler = prescaler_for(Hertz(frequency));
+            assert_eq!(prescaler, Err(Error::PrescalerOutOfRange));
+        }
+    }
+
+    #[test]
+    fn prescaler_rounding() {
+        // 32768 / 1000 = 32.768, the closest divider is 33
+        assert_eq!(prescaler_for(Hertz(1_000)), Ok(32));
+        // 32768 / 100 = 327.68, the closest divider is 328
+        assert_eq!(prescaler_for(Hertz(100)), Ok(327));
+        // 32768 / 21845 = 1.5, halfway rounds up
+        assert_eq!(prescaler_for(Hertz(21_845)), Ok(1));
+        assert_eq!(prescaler_for(Hertz(21_846)), Ok(0));
+
+        // Powers of two divide the clock exactly
+        for shift in 0..=12 {
+            let frequency = Hertz(LFCLK_FREQ >> shift);
+            assert_eq!(prescaler_for(frequency), Ok((1 << shift) - 1));
+        }
+    }
+
+    #[test]
+    fn ticks_and_durations() {
+        // 1 kHz divides the clock by 33, ticks of 1007.08 us each
+        let prescaler = prescaler_for(Hertz(1_000)).unwrap();
+        assert_eq!(ticks_in(prescaler, MicroSeconds(1_000_000)), Some(992));
+        assert_eq!(ticks_in(prescaler, MicroSeconds(1_007)), Some(0));
+        assert_eq!(ticks_in(prescaler, MicroSeconds(1_008)), Some(1));
+        assert_eq!(duration_of(prescaler, 992), MicroSeconds(999_023));
+        assert_eq!(duration_of(prescaler, 1), MicroSeconds(1_007));
+
+        // 32768 / 3 = 10922.67 Hz, truncating to 10922 Hz would drift
+        assert_eq!(ticks_in(2, MicroSeconds(3_000_000)), Some(32_768));
+        assert_eq!(duration_of(2, 32_768), MicroSeconds(3_000_000));
+
+        // The range of the 24-bit compare registers
+        assert_eq!(ticks_in(0, MicroSeconds(511_999_970)), Some(COUNTER_MASK));
+        assert_eq!(ticks_in(0, MicroSeconds(512_000_000)), None);
+        assert_eq!(
+            duration_of(MAX_PRESCALER, COUNTER_MASK),
+            MicroSeconds(u32::max_value())
+        );
+    }
+
+    #[test]
+    fn wrapping_arithmetic() {
+        assert_eq!(wrapping_ticks(0, 5), 5);
+        assert_eq!(wrapping_ticks(10, 10), 0);
+        assert_eq!(wrapping_ticks(COUNTER_MASK, 0), 1);
+        assert_eq!(wrapping_ticks(COUNTER_MASK - 2, 3), 6);
+        assert_eq!(wrapping_ticks(5, 0), COUNTER_MASK - 4);
+
+        // Bits above the counter are ignored
+        let wrap = 1 << COUNTER_BITS;
+        assert_eq!(wrapping_ticks(wrap, wrap + 3), 3);
+        assert_eq!(wrapping_ticks(0, COUNTER_MASK + 2), 1);
+    }
+}
@@ -1,5 +1,20 @@
+//! HAL interface to the SAADC peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 37
+//! - nrf52840: Section 6.23
+//!
+//! The SAADC has eight channels, each sampling a positive input, either
+//! against ground or against a negative input (differential mode), with its
+//! own gain, reference and acquisition time. `OneShot` samples a single
+//! analog pin through channel 0. Channels configured with
+//! `Saadc::configure_channel` are sampled together, in channel order, by
+//! `Saadc::scan` or continuously into a pair of DMA buffers with
+//! `Saadc::into_continuous`.
 use crate::{
     gpio::{Floating, Input},
+    ppi,
     target::{saadc, SAADC},
 };
 use core::{
@@ -8,18 +23,28 @@ use core::{
     sync::atomic::{compiler_fence, Ordering::SeqCst},
 };
 use embedded_hal::adc::{Channel, OneShot};
+use fpa::I22F10;
+use void::Void;
 
 pub use crate::target::saadc::{
     ch::config::{
         GAINW as Gain, REFSELW as Reference, RESPW as Resistor, TACQW as Time,
     },
+    ch::pseln::PSELNW as NegativeInput,
+    ch::pselp::PSELPW as PositiveInput,
     oversample::OVERSAMPLEW as Oversample,
     resolution::VALW as Resolution,
 };
 
-// Only 1 channel is allowed right now, a discussion needs to be had as to how
-// multiple channels should work (See "scan mode" in the datasheet)
-// Issue: https://github.com/nrf-rs/nrf52-hal/issues/82
+/// Number of SAADC channels
+pub const NUM_CHANNELS: usize = 8;
+
+/// Largest number of samples in a DMA buffer
+pub const MAX_SAMPLES: usize = 0x7FFF;
+
+/// Bit of the CH[0].LIMITH event in INTEN, followed by CH[0].LIMITL and the
+/// other channels
+const INTEN_LIMIT_OFFSET: u32 = 6;
 
 pub trait SaadcExt: Deref<Target = saadc::RegisterBlock> + Sized {
     fn constrain(self) -> Saadc;
@@ -31,7 +56,11 @@ impl SaadcExt for SAADC {
     }
 }
 
-pub struct Saadc(SAADC);
+pub struct Saadc(
+    SAADC,
+    // CH[0].CONFIG as set up from the `SaadcConfig`, used by `OneShot`
+    u32,
+);
 
 impl Saadc {
     pub fn new(saadc: SAADC, config: SaadcConfig) -> Self {
@@ -71,11 +100,365 @@ impl Saadc {
         });
         saadc.ch[0].pseln.write(|w| w.pseln().nc());
 
-        // Calibrate
-        saadc.tasks_calibrateoffset.write(|w| unsafe { w.bits(1) });
-        while saadc.events_calibratedone.read().bits() == 0 {}
+        let oneshot_config = saadc.ch[0].config.read().bits();
+        let mut saadc = Saadc(saadc, oneshot_config);
+        saadc.calibrate();
+
+        saadc
+    }
+
+    /// Run the offset calibration and wait for it to complete
+    ///
+    /// The offset drifts with temperature, so calibration should be repeated
+    /// when the temperature has changed by more than about 10 °C.
+    pub fn calibrate(&mut self) {
+        self.start_calibration();
+        nb::block!(self.wait_calibration()).unwrap()
+    }
+
+    /// Start the offset calibration
+    ///
+    /// No sampling must be in progress. Completion is reported by
+    /// `wait_calibration`, or by the CALIBRATEDONE event.
+    pub fn start_calibration(&mut self) {
+        self.0.events_calibratedone.reset();
+        self.0.tasks_calibrateoffset.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Has the offset calibration completed? (non-blocking)
+    pub fn wait_calibration(&mut self) -> nb::Result<(), Void> {
+        if self.0.events_calibratedone.read().bits() == 0 {
+            return Err(nb::Error::WouldBlock);
+        }
+
+        self.0.events_calibratedone.reset();
+        Ok(())
+    }
+
+    /// The scale of the samples of channel `ch`, as configured
+    ///
+    /// `vdd_mv` is the supply voltage in millivolts, used when the channel's
+    /// reference is VDD/4. Channel 0 holds the `SaadcConfig` settings used
+    /// by `OneShot`, unless configured with `configure_channel`.
+    pub fn scale(&self, ch: usize, vdd_mv: u32) -> Result<Scale, Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+
+        let config = self.0.ch[ch].config.read();
+        let gain = match config.gain().bits() {
+            0 => (1, 6),
+            1 => (1, 5),
+            2 => (1, 4),
+            3 => (1, 3),
+            4 => (1, 2),
+            5 => (1, 1),
+            6 => (2, 1),
+            _ => (4, 1),
+        };
+        let reference_mv = if config.refsel().bit() {
+            vdd_mv / 4
+        } else {
+            INTERNAL_REFERENCE_MV
+        };
+        let resolution = match self.0.resolution.read().val().bits() {
+            0 => 8,
+            1 => 10,
+            2 => 12,
+            _ => 14,
+        };
+
+        Ok(Scale {
+            reference_mv,
+            gain,
+            resolution,
+            differential: config.mode().bit(),
+        })
+    }
+
+    /// Configure channel `ch` and include it in scans
+    pub fn configure_channel(&mut self, ch: usize, config: ChannelConfig) -> Result<(), Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+
+        let ChannelConfig {
+            positive,
+            negative,
+            reference,
+            gain,
+            resistor,
+            time,
+        } = config;
+        let differential = negative.is_some();
+
+        self.0.ch[ch].config.write(|w| {
+            let w = w
+                .refsel()
+                .variant(reference)
+                .gain()
+                .variant(gain)
+                .tacq()
+                .variant(time)
+                .resp()
+                .variant(resistor)
+                .resn()
+                .bypass()
+                .burst()
+                .enabled();
+            if differential {
+                w.mode().diff()
+            } else {
+                w.mode().se()
+            }
+        });
+        self.0.ch[ch].pseln.write(|w| match negative {
+            Some(input) => w.pseln().variant(input),
+            None => w.pseln().nc(),
+        });
+        self.0.ch[ch].pselp.write(|w| w.pselp().variant(positive));
+
+        Ok(())
+    }
+
+    /// Exclude channel `ch` from scans
+    pub fn disable_channel(&mut self, ch: usize) -> Result<(), Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+
+        self.0.ch[ch].pselp.write(|w| w.pselp().nc());
+        self.0.ch[ch].pseln.write(|w| w.pseln().nc());
+
+        Ok(())
+    }
+
+    /// Number of channels included in scans
+    pub fn enabled_channels(&self) -> usize {
+        self.0
+            .ch
+            .iter()
+            .filter(|ch| !ch.pselp.read().pselp().is_nc())
+            .count()
+    }
+
+    /// Sample all enabled channels once
+    ///
+    /// `buf` receives one sample per enabled channel, in channel order, so its
+    /// length must equal `enabled_channels`, which must not be zero. Samples
+    /// of differential channels may be negative.
+    pub fn scan(&mut self, buf: &mut [i16]) -> Result<(), Error> {
+        let channels = self.enabled_channels();
+        if channels == 0 || buf.len() != channels {
+            return Err(Error::BufferLength);
+        }
+
+        self.set_buffer(buf);
+
+        // Conservative compiler fence to prevent starting the ADC before the
+        // pointer and maxcount have been set
+        compiler_fence(SeqCst);
+
+        self.0.tasks_start.write(|w| unsafe { w.bits(1) });
+        self.0.tasks_sample.write(|w| unsafe { w.bits(1) });
+
+        while self.0.events_end.read().bits() == 0 {}
+        self.0.events_end.reset();
+
+        // Second fence to prevent optimizations creating issues with the EasyDMA-modified `buf`
+        compiler_fence(SeqCst);
+
+        Ok(())
+    }
+
+    /// Set the limits of channel `ch`, in the unit of its samples
+    ///
+    /// The limit events fire when a sample is above `high` or below `low`.
+    pub fn set_limits(&mut self, ch: usize, low: i16, high: i16) -> Result<(), Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+
+        self.0.ch[ch]
+            .limit
+            .write(|w| unsafe { w.bits(u32::from(high as u16) << 16 | u32::from(low as u16)) });
+
+        Ok(())
+    }
+
+    /// Enable the interrupt for a limit event of channel `ch`
+    pub fn enable_limit_interrupt(&mut self, ch: usize, limit: Limit) -> Result<(), Error> {
+        let mask = limit_mask(ch, limit)?;
+        self.0.intenset.write(|w| unsafe { w.bits(mask) });
+
+        Ok(())
+    }
+
+    /// Disable the interrupt for a limit event of channel `ch`
+    pub fn disable_limit_interrupt(&mut self, ch: usize, limit: Limit) -> Result<(), Error> {
+        let mask = limit_mask(ch, limit)?;
+        self.0.intenclr.write(|w| unsafe { w.bits(mask) });
+
+        Ok(())
+    }
+
+    /// Has a limit event of channel `ch` fired? The event is cleared.
+    pub fn is_limit_triggered(&mut self, ch: usize, limit: Limit) -> Result<bool, Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+
+        let events = &self.0.events_ch[ch];
+        let triggered = match limit {
+            Limit::High => events.limith.read().bits() != 0,
+            Limit::Low => events.limitl.read().bits() != 0,
+        };
+        if triggered {
+            match limit {
+                Limit::High => events.limith.reset(),
+                Limit::Low => events.limitl.reset(),
+            }
+        }
+
+        Ok(triggered)
+    }
+
+    /// Sample the enabled channels continuously into two alternating buffers
+    ///
+    /// Each buffer holds whole scans, so its length must be a multiple of
+    /// `enabled_channels`. While one buffer is filled, the other one is handed
+    /// out by `Continuous::process_interrupt`. The interrupt must be served
+    /// before the buffer being filled is full, hence the buffers should hold
+    /// more than a few samples.
+    ///
+    /// The SAADC moves on to the next buffer through `restart`, a PPI channel
+    /// connecting its END event to its START task, which is returned by
+    /// `Continuous::stop`.
+    pub fn into_continuous<C>(
+        self,
+        buffers: [&'static mut [i16]; 2],
+        rate: SampleRate,
+        restart: ppi::Channel<C>,
+    ) -> Result<Continuous<C>, (Self, ppi::Channel<C>, Error)>
+    where
+        C: ppi::ChannelNum,
+    {
+        let channels = self.enabled_channels();
+        if channels == 0 {
+            return Err((self, restart, Error::BufferLength));
+        }
+        for buf in buffers.iter() {
+            if buf.is_empty() || buf.len() % channels != 0 || buf.len() > MAX_SAMPLES {
+                return Err((self, restart, Error::BufferLength));
+            }
+        }
+
+        match rate {
+            // The internal timer can only be used with a single channel
+            SampleRate::Internal(_) if channels != 1 => {
+                return Err((self, restart, Error::InvalidChannel));
+            }
+            SampleRate::Internal(cc) => {
+                if cc < 80 || cc > 2047 {
+                    return Err((self, restart, Error::InvalidSampleRate));
+                }
+                self.0
+                    .samplerate
+                    .write(|w| unsafe { w.cc().bits(cc).mode().timers() });
+            }
+            SampleRate::Task => self.0.samplerate.write(|w| w.mode().task()),
+        }
+
+        let mut continuous = Continuous {
+            saadc: self,
+            buffers,
+            restart,
+            current: 0,
+            internal: match rate {
+                SampleRate::Internal(_) => true,
+                SampleRate::Task => false,
+            },
+        };
+        continuous.start();
+
+        Ok(continuous)
+    }
+
+    fn set_buffer(&mut self, buf: &mut [i16]) {
+        self.0
+            .result
+            .ptr
+            .write(|w| unsafe { w.ptr().bits(buf.as_mut_ptr() as u32) });
+        self.0
+            .result
+            .maxcnt
+            .write(|w| unsafe { w.maxcnt().bits(buf.len() as _) });
+    }
+
+    /// The CH[n].LIMITH event, for use as a PPI event endpoint
+    pub fn event_limit_high(&self, ch: usize) -> Result<&saadc::events_ch::LIMITH, Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(&self.0.events_ch[ch].limith)
+    }
+
+    /// The CH[n].LIMITL event, for use as a PPI event endpoint
+    pub fn event_limit_low(&self, ch: usize) -> Result<&saadc::events_ch::LIMITL, Error> {
+        if ch >= NUM_CHANNELS {
+            return Err(Error::InvalidChannel);
+        }
+        Ok(&self.0.events_ch[ch].limitl)
+    }
+
+    /// The STARTED event, for use as a PPI event endpoint
+    pub fn event_started(&self) -> &saadc::EVENTS_STARTED {
+        &self.0.events_started
+    }
+
+    /// The END event, for use as a PPI event endpoint
+    pub fn event_end(&self) -> &saadc::EVENTS_END {
+        &self.0.events_end
+    }
+
+    /// The DONE event, for use as a PPI event endpoint
+    pub fn event_done(&self) -> &saadc::EVENTS_DONE {
+        &self.0.events_done
+    }
+
+    /// The RESULTDONE event, for use as a PPI event endpoint
+    pub fn event_result_done(&self) -> &saadc::EVENTS_RESULTDONE {
+        &self.0.events_resultdone
+    }
+
+    /// The CALIBRATEDONE event, for use as a PPI event endpoint
+    pub fn event_calibrate_done(&self) -> &saadc::EVENTS_CALIBRATEDONE {
+        &self.0.events_calibratedone
+    }
+
+    /// The STOPPED event, for use as a PPI event endpoint
+    pub fn event_stopped(&self) -> &saadc::EVENTS_STOPPED {
+        &self.0.events_stopped
+    }
 
-        Saadc(saadc)
+    /// The START task, for use as a PPI task endpoint
+    pub fn task_start(&self) -> &saadc::TASKS_START {
+        &self.0.tasks_start
+    }
+
+    /// The SAMPLE task, for use as a PPI task endpoint
+    pub fn task_sample(&self) -> &saadc::TASKS_SAMPLE {
+        &self.0.tasks_sample
+    }
+
+    /// The STOP task, for use as a PPI task endpoint
+    pub fn task_stop(&self) -> &saadc::TASKS_STOP {
+        &self.0.tasks_stop
+    }
+
+    /// The CALIBRATEOFFSET task, for use as a PPI task endpoint
+    pub fn task_calibrate_offset(&self) -> &saadc::TASKS_CALIBRATEOFFSET {
+        &self.0.tasks_calibrateoffset
     }
 }
 
@@ -102,12 +485,269 @@ impl Default for SaadcConfig {
     }
 }
 
+/// Configuration of a single channel
+pub struct ChannelConfig {
+    /// The sampled input
+    pub positive: PositiveInput,
+    /// The input the positive one is sampled against, ground if `None`
+    pub negative: Option<NegativeInput>,
+    pub reference: Reference,
+    pub gain: Gain,
+    pub resistor: Resistor,
+    pub time: Time,
+}
+
+impl ChannelConfig {
+    /// Sample `positive` against ground, with the default settings of
+    /// `SaadcConfig`
+    pub fn single_ended(positive: PositiveInput) -> Self {
+        ChannelConfig {
+            positive,
+            negative: None,
+            reference: Reference::VDD1_4,
+            gain: Gain::GAIN1_4,
+            resistor: Resistor::BYPASS,
+            time: Time::_20US,
+        }
+    }
+
+    /// Sample `positive` against `negative`, with the default settings of
+    /// `SaadcConfig`
+    pub fn differential(positive: PositiveInput, negative: NegativeInput) -> Self {
+        ChannelConfig {
+            negative: Some(negative),
+            ..ChannelConfig::single_ended(positive)
+        }
+    }
+}
+
+/// What triggers sampling in continuous mode
+pub enum SampleRate {
+    /// The internal timer, sampling every `cc` 16 MHz clock cycles
+    ///
+    /// `cc` must be in the range 80 to 2047, and only one channel may be
+    /// enabled.
+    Internal(u16),
+    /// The SAMPLE task, typically triggered through PPI
+    Task,
+}
+
+/// A limit event of a channel
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Limit {
+    /// A sample was above the high limit
+    High,
+    /// A sample was below the low limit
+    Low,
+}
+
+fn limit_mask(ch: usize, limit: Limit) -> Result<u32, Error> {
+    if ch >= NUM_CHANNELS {
+        return Err(Error::InvalidChannel);
+    }
+
+    let bit = INTEN_LIMIT_OFFSET + 2 * ch as u32;
+    Ok(match limit {
+        Limit::High => 1 << bit,
+        Limit::Low => 1 << (bit + 1),
+    })
+}
+
+/// The SAADC sampling continuously into two alternating buffers
+pub struct Continuous<C> {
+    saadc: Saadc,
+    buffers: [&'static mut [i16]; 2],
+    restart: ppi::Channel<C>,
+    /// Index of the buffer being filled
+    current: usize,
+    internal: bool,
+}
+
+impl<C> Continuous<C>
+where
+    C: ppi::ChannelNum,
+{
+    fn start(&mut self) {
+        let saadc = &self.saadc.0;
+
+        saadc.events_started.reset();
+        saadc.events_end.reset();
+
+        let buf = &mut self.buffers[self.current];
+        self.saadc.set_buffer(buf);
+
+        // Restart with the next buffer as soon as one is full
+        self.restart
+            .connect(&self.saadc.0.events_end, &self.saadc.0.tasks_start);
+
+        compiler_fence(SeqCst);
+        self.saadc.0.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        // In timer mode, the SAMPLE task starts the internal timer
+        if self.internal {
+            self.saadc.0.tasks_sample.write(|w| unsafe { w.bits(1) });
+        }
+    }
+
+    /// Enable the STARTED and END interrupts, which drive `process_interrupt`
+    pub fn enable_interrupts(&mut self) {
+        self.saadc
+            .0
+            .intenset
+            .write(|w| w.started().set().end().set());
+    }
+
+    /// Disable the STARTED and END interrupts
+    pub fn disable_interrupts(&mut self) {
+        self.saadc
+            .0
+            .intenclr
+            .write(|w| w.started().clear().end().clear());
+    }
+
+    /// Handle the SAADC interrupt
+    ///
+    /// Returns the samples of a buffer that has just been filled. The buffer
+    /// is filled again once the other one is full, so the samples must be
+    /// used before that.
+    ///
+    /// END only fires once a buffer is full, as the PPI channel restarts the
+    /// SAADC right away. By the time the interrupt is served, AMOUNT already
+    /// counts the samples of the next buffer, so the whole buffer is
+    /// returned.
+    pub fn process_interrupt(&mut self) -> Option<&[i16]> {
+        let saadc = &self.saadc.0;
+        let mut done = None;
+
+        if saadc.events_end.read().bits() != 0 {
+            saadc.events_end.reset();
+
+            // Second fence to prevent optimizations creating issues with the EasyDMA-modified buffer
+            compiler_fence(SeqCst);
+
+            done = Some(self.current);
+            self.current ^= 1;
+        }
+
+        if saadc.events_started.read().bits() != 0 {
+            saadc.events_started.reset();
+
+            // The pointer is double buffered, queue the other buffer for when
+            // the current one is full
+            let next = &mut self.buffers[self.current ^ 1];
+            saadc
+                .result
+                .ptr
+                .write(|w| unsafe { w.ptr().bits(next.as_mut_ptr() as u32) });
+            saadc
+                .result
+                .maxcnt
+                .write(|w| unsafe { w.maxcnt().bits(next.len() as _) });
+        }
+
+        match done {
+            Some(i) => Some(&self.buffers[i][..]),
+            None => None,
+        }
+    }
+
+    /// The saadc peripheral, for its limit and PPI endpoint methods
+    pub fn saadc(&mut self) -> &mut Saadc {
+        &mut self.saadc
+    }
+
+    /// Stop sampling, returning the SAADC, the buffers and the PPI channel
+    pub fn stop(mut self) -> (Saadc, [&'static mut [i16]; 2], ppi::Channel<C>) {
+        self.restart.disable();
+
+        let saadc = &self.saadc.0;
+        saadc.intenclr.write(|w| w.started().clear().end().clear());
+        saadc.tasks_stop.write(|w| unsafe { w.bits(1) });
+        while saadc.events_stopped.read().bits() == 0 {}
+        saadc.events_stopped.reset();
+        saadc.samplerate.write(|w| w.mode().task());
+
+        compiler_fence(SeqCst);
+
+        (self.saadc, self.buffers, self.restart)
+    }
+}
+
+/// Voltage of the internal reference in millivolts
+pub const INTERNAL_REFERENCE_MV: u32 = 600;
+
+/// How samples relate to the input voltage
+///
+/// A sample is `(V(P) - V(N)) * gain / reference * 2^(resolution - m)`, where
+/// `m` is 1 in differential mode and 0 otherwise.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Scale {
+    /// Reference voltage in millivolts
+    pub reference_mv: u32,
+    /// Gain as a fraction, numerator and denominator
+    pub gain: (u32, u32),
+    /// Resolution in bits
+    pub resolution: u32,
+    /// Are samples taken against a negative input?
+    pub differential: bool,
+}
+
+impl Scale {
+    /// Convert `sample` to millivolts
+    pub fn millivolts(&self, sample: i16) -> I22F10 {
+        let (num, den) = self.gain;
+        let shift = self.resolution - if self.differential { 1 } else { 0 };
+
+        // Intermediate product needs up to 15 + 12 + 3 + 10 bits
+        let scaled = (i64::from(sample) * i64::from(self.reference_mv) * i64::from(den)) << 10;
+        let bits = scaled / (i64::from(num) << shift);
+
+        I22F10::from_bits(bits as i32)
+    }
+
+    /// Largest voltage that can be measured, in millivolts
+    pub fn full_scale_mv(&self) -> u32 {
+        let (num, den) = self.gain;
+        self.reference_mv * den / num
+    }
+}
+
+/// Error types associated with the SAADC peripheral interface
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The channel does not exist, or can not be used like this
+    InvalidChannel,
+    /// The buffer does not match the number of enabled channels
+    BufferLength,
+    /// The internal sample rate is out of range
+    InvalidSampleRate,
+}
+
+/// Samples a single pin through channel 0, with the `SaadcConfig` settings
+///
+/// The channels configured for scans are set aside during the conversion and
+/// restored afterwards, so `OneShot` and `Saadc::scan` can be mixed.
 impl<PIN> OneShot<Saadc, u16, PIN> for Saadc
 where
     PIN: Channel<Saadc, ID = u8>,
 {
     type Error = ();
     fn read(&mut self, _pin: &mut PIN) -> nb::Result<u16, Self::Error> {
+        // Set the scan channels aside
+        let mut pselp = [0; NUM_CHANNELS];
+        for (bits, ch) in pselp.iter_mut().zip(self.0.ch.iter()) {
+            *bits = ch.pselp.read().bits();
+            ch.pselp.write(|w| w.pselp().nc());
+        }
+        let config = self.0.ch[0].config.read().bits();
+        let pseln = self.0.ch[0].pseln.read().bits();
+
+        let oneshot_config = self.1;
+        self.0.ch[0]
+            .config
+            .write(|w| unsafe { w.bits(oneshot_config) });
+        self.0.ch[0].pseln.write(|w| w.pseln().nc());
+
         match PIN::channel() {
             0 => self.0.ch[0].pselp.write(|w| w.pselp().analog_input0()),
             1 => self.0.ch[0].pselp.write(|w| w.pselp().analog_input1()),
@@ -141,9 +781,17 @@ where
 
         while self.0.events_end.read().bits() == 0 {}
         self.0.events_end.reset();
+        let amount = self.0.result.amount.read().bits();
 
-        // Will only occur if more than one channel has been enabled
-        if self.0.result.amount.read().bits() != 1 {
+        // Restore the scan channels
+        self.0.ch[0].config.write(|w| unsafe { w.bits(config) });
+        self.0.ch[0].pseln.write(|w| unsafe { w.bits(pseln) });
+        for (bits, ch) in pselp.iter().zip(self.0.ch.iter()) {
+            ch.pselp.write(|w| unsafe { w.bits(*bits) });
+        }
+
+        // Should not occur, as all other channels have been set aside
+        if amount != 1 {
             return Err(nb::Error::Other(()));
         }
 
@@ -178,3 +826,103 @@ channel_mappings! {
     6 => crate::gpio::p0::P0_30<Input<Floating>>,
     7 => crate::gpio::p0::P0_31<Input<Floating>>
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scale(reference_mv: u32, gain: (u32, u32), resolution: u32, differential: bool) -> Scale {
+        Scale {
+            reference_mv,
+            gain,
+            resolution,
+            differential,
+        }
+    }
+
+    fn mv(millivolts: i32) -> I22F10 {
+        I22F10::from_bits(millivolts << 10)
+    }
+
+    #[test]
+    fn default_config() {
+        // VDD/4 reference at 3 V, gain 1/4, 14 bits
+        let s = scale(750, (1, 4), 14, false);
+        assert_eq!(s.full_scale_mv(), 3000);
+        assert!(s.millivolts(0) == mv(0));
+        assert!(s.millivolts(8192) == mv(1500));
+        assert!(s.millivolts(16384) == mv(3000));
+
+        // 3000 mV / 2^14 per step, rounded down to 1/1024 mV
+        assert!(s.millivolts(1) == I22F10::from_bits(187));
+    }
+
+    #[test]
+    fn gain() {
+        for &(gain, full_scale) in &[
+            ((1, 6), 3600),
+            ((1, 5), 3000),
+            ((1, 4), 2400),
+            ((1, 3), 1800),
+            ((1, 2), 1200),
+            ((1, 1), 600),
+        ] {
+            let s = scale(INTERNAL_REFERENCE_MV, gain, 12, false);
+            assert_eq!(s.full_scale_mv(), full_scale);
+            assert!(s.millivolts(2048) == mv(full_scale as i32 / 2));
+        }
+
+        // Gains above one give fractions of a millivolt
+        let s = scale(INTERNAL_REFERENCE_MV, (2, 1), 12, false);
+        assert_eq!(s.full_scale_mv(), 300);
+        assert!(s.millivolts(1) == I22F10::from_bits(75));
+        let s = scale(INTERNAL_REFERENCE_MV, (4, 1), 12, false);
+        assert_eq!(s.full_scale_mv(), 150);
+        assert!(s.millivolts(4096) == mv(150));
+        assert!(s.millivolts(2048) == mv(75));
+    }
+
+    #[test]
+    fn reference() {
+        // VDD/4 follows the supply, the internal reference does not
+        for &vdd_mv in &[1800, 3000, 3600] {
+            let s = scale(vdd_mv / 4, (1, 4), 12, false);
+            assert_eq!(s.full_scale_mv(), vdd_mv);
+            assert!(s.millivolts(4096) == mv(vdd_mv as i32));
+        }
+    }
+
+    #[test]
+    fn resolution() {
+        for &resolution in &[8, 10, 12, 14] {
+            let s = scale(INTERNAL_REFERENCE_MV, (1, 6), resolution, false);
+            let half = 1 << (resolution - 1);
+            assert!(s.millivolts(half) == mv(1800));
+            assert!(s.millivolts(half / 2) == mv(900));
+        }
+
+        // One step at 8 bits is 3600 / 256 = 14.0625 mV
+        let s = scale(INTERNAL_REFERENCE_MV, (1, 6), 8, false);
+        assert!(s.millivolts(1) == I22F10::from_bits((14 << 10) | 64));
+    }
+
+    #[test]
+    fn differential() {
+        // One bit of the resolution holds the sign
+        let s = scale(INTERNAL_REFERENCE_MV, (1, 6), 12, true);
+        assert!(s.millivolts(2047) < mv(3600));
+        assert!(s.millivolts(1024) == mv(1800));
+        assert!(s.millivolts(-1024) == mv(-1800));
+        assert!(s.millivolts(-2048) == mv(-3600));
+
+        // 3600 / 2048 = 1.7578125 mV per step, exact in 1/1024 mV
+        assert!(s.millivolts(-1) == I22F10::from_bits(-1800));
+        assert!(s.millivolts(1) == I22F10::from_bits(1800));
+
+        // Inexact steps round towards zero on both sides
+        let s = scale(750, (1, 4), 14, true);
+        assert!(s.millivolts(1) == I22F10::from_bits(375));
+        assert!(s.millivolts(-1) == I22F10::from_bits(-375));
+        assert!(s.millivolts(-8192) == mv(-3000));
+    }
+}
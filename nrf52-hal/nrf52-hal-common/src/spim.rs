@@ -16,15 +16,41 @@ use crate::target::{SPIM1, SPIM2};
 
 use crate::gpio::{Floating, Input, Output, Pin, PushPull};
 use crate::prelude::*;
+use crate::time::{self, Hertz};
 use crate::target_constants::{
     EASY_DMA_SIZE, FORCE_COPY_BUFFER_SIZE, SRAM_LOWER, SRAM_UPPER,
 };
 
+pub use crate::time::IntoFrequency;
+
+impl IntoFrequency<Frequency> for Frequency {
+    fn into_frequency(self) -> Frequency {
+        self
+    }
+}
+
+impl IntoFrequency<Frequency> for Hertz {
+    fn into_frequency(self) -> Frequency {
+        let rates = [
+            125_000, 250_000, 500_000, 1_000_000, 2_000_000, 4_000_000, 8_000_000,
+        ];
+        match time::highest_not_above(self.0, &rates) {
+            0 => Frequency::K125,
+            1 => Frequency::K250,
+            2 => Frequency::K500,
+            3 => Frequency::M1,
+            4 => Frequency::M2,
+            5 => Frequency::M4,
+            _ => Frequency::M8,
+        }
+    }
+}
+
 pub trait SpimExt: Deref<Target = spim0::RegisterBlock> + Sized {
-    fn constrain(
+    fn constrain<F: IntoFrequency<Frequency>>(
         self,
         pins: Pins,
-        frequency: Frequency,
+        frequency: F,
         mode: Mode,
         orc: u8,
     ) -> Spim<Self>;
@@ -34,7 +60,7 @@ macro_rules! impl_spim_ext {
     ($($spim:ty,)*) => {
         $(
             impl SpimExt for $spim {
-                fn constrain(self, pins: Pins, frequency: Frequency, mode: Mode, orc: u8) -> Spim<Self> {
+                fn constrain<F: IntoFrequency<Frequency>>(self, pins: Pins, frequency: F, mode: Mode, orc: u8) -> Spim<Self> {
                     Spim::new(self, pins, frequency, mode, orc)
                 }
             }
@@ -137,13 +163,15 @@ impl<T> Spim<T>
 where
     T: SpimExt,
 {
-    pub fn new(
+    pub fn new<F: IntoFrequency<Frequency>>(
         spim: T,
         pins: Pins,
-        frequency: Frequency,
+        frequency: F,
         mode: Mode,
         orc: u8,
     ) -> Self {
+        let frequency = frequency.into_frequency();
+
         // Select pins
         spim.psel.sck.write(|w| {
             let w = unsafe { w.pin().bits(pins.sck.pin) };
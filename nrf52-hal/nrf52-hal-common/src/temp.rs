@@ -1,10 +1,32 @@
 //! Temperature sensor interface.
+//!
+//! The sensor has a resolution of 0.25 °C. Besides the `I30F2` fixed point
+//! results, measurements are available as integer millidegrees Celsius,
+//! which need no support from the `fpa` crate to be displayed or logged.
+//!
+//! A measurement takes about 36 µs. To not block, it can be started with
+//! `start_measurement` and retrieved when the DATARDY event fires, with the
+//! TEMP interrupt enabled through `enable_interrupt`:
+//!
+//! ```ignore
+//! #[interrupt(resources = [TEMP, AVERAGE])]
+//! fn TEMP() {
+//!     let mdeg = resources.TEMP.read_millidegrees().unwrap();
+//!     if let Some(mdeg) = resources.AVERAGE.push(mdeg) {
+//!         // an average over `AVERAGE.samples()` measurements
+//!     }
+//!     resources.TEMP.start_measurement();
+//! }
+//! ```
 
-use crate::target::TEMP;
+use crate::target::{temp, TEMP};
 use fpa::I30F2;
 use nb;
 use void::Void;
 
+/// Millidegrees Celsius per step of the TEMP register
+const MILLIDEGREES_PER_STEP: i32 = 250;
+
 /// Integrated temperature sensor.
 pub struct Temp(TEMP);
 
@@ -14,6 +36,11 @@ impl Temp {
         Temp(raw)
     }
 
+    /// Releases the temperature sensor's register block.
+    pub fn free(self) -> TEMP {
+        self.0
+    }
+
     /// Starts a new measurement and blocks until completion.
     ///
     /// If a measurement was already started, it will be canceled.
@@ -24,6 +51,31 @@ impl Temp {
         nb::block!(self.read()).unwrap()
     }
 
+    /// Starts a new measurement and blocks until completion.
+    ///
+    /// Returns the measured temperature in millidegrees Celsius.
+    pub fn measure_millidegrees(&mut self) -> i32 {
+        self.stop_measurement();
+        self.start_measurement();
+
+        nb::block!(self.read_millidegrees()).unwrap()
+    }
+
+    /// Averages `samples` blocking measurements.
+    ///
+    /// Returns the mean temperature in millidegrees Celsius, rounded to the
+    /// nearest. Averaging smooths out the noise of the sensor, and yields a
+    /// finer resolution than a single measurement.
+    pub fn measure_average(&mut self, samples: u32) -> i32 {
+        let mut average = Average::new(samples);
+        loop {
+            let mdeg = self.measure_millidegrees();
+            if let Some(mean) = average.push(mdeg) {
+                return mean;
+            }
+        }
+    }
+
     /// Kicks off a temperature measurement.
     ///
     /// The measurement can be retrieved by calling `read`.
@@ -47,12 +99,177 @@ impl Temp {
     ///
     /// Returns the measured temperature in °C.
     pub fn read(&mut self) -> nb::Result<I30F2, Void> {
-        if self.0.events_datardy.read().bits() == 0 {
+        let raw = self.read_raw()?;
+        Ok(I30F2::from_bits(raw))
+    }
+
+    /// Tries to read a started measurement (non-blocking).
+    ///
+    /// Before calling this, `start_measurement` must be called.
+    ///
+    /// Returns the measured temperature in millidegrees Celsius.
+    pub fn read_millidegrees(&mut self) -> nb::Result<i32, Void> {
+        let raw = self.read_raw()?;
+        Ok(raw * MILLIDEGREES_PER_STEP)
+    }
+
+    /// Returns whether a started measurement is ready to be read.
+    pub fn is_ready(&self) -> bool {
+        self.0.events_datardy.read().bits() != 0
+    }
+
+    /// Enables the TEMP interrupt, fired when a measurement is ready.
+    pub fn enable_interrupt(&mut self) {
+        self.0.intenset.write(|w| w.datardy().set());
+    }
+
+    /// Disables the TEMP interrupt.
+    pub fn disable_interrupt(&mut self) {
+        self.0.intenclr.write(|w| w.datardy().clear());
+    }
+
+    /// Returns the DATARDY event, for use as a PPI event endpoint.
+    pub fn event_datardy(&self) -> &temp::EVENTS_DATARDY {
+        &self.0.events_datardy
+    }
+
+    /// Returns the START task, for use as a PPI task endpoint.
+    pub fn task_start(&self) -> &temp::TASKS_START {
+        &self.0.tasks_start
+    }
+
+    /// Reads the TEMP register in steps of 0.25 °C, clearing the event.
+    fn read_raw(&mut self) -> nb::Result<i32, Void> {
+        if !self.is_ready() {
             return Err(nb::Error::WouldBlock);
-        } else {
-            self.0.events_datardy.reset(); // clear event
-            let raw = self.0.temp.read().bits();
-            Ok(I30F2::from_bits(raw as i32))
         }
+
+        self.0.events_datardy.reset(); // clear event
+        Ok(self.0.temp.read().bits() as i32)
+    }
+}
+
+/// Averages a fixed number of measurements, in millidegrees Celsius.
+///
+/// Meant to be fed from the TEMP interrupt, one measurement at a time.
+#[derive(Clone, Debug)]
+pub struct Average {
+    len: u32,
+    count: u32,
+    sum: i64,
+}
+
+impl Average {
+    /// Creates an average over `len` measurements, at least one.
+    pub fn new(len: u32) -> Self {
+        Average {
+            len: len.max(1),
+            count: 0,
+            sum: 0,
+        }
+    }
+
+    /// Returns the number of measurements averaged.
+    pub fn samples(&self) -> u32 {
+        self.len
+    }
+
+    /// Adds a measurement.
+    ///
+    /// Once `len` measurements have been added, returns their mean rounded to
+    /// the nearest millidegree, and starts over.
+    pub fn push(&mut self, millidegrees: i32) -> Option<i32> {
+        self.sum += i64::from(millidegrees);
+        self.count += 1;
+        if self.count < self.len {
+            return None;
+        }
+
+        let len = i64::from(self.len);
+        let half = if self.sum < 0 { -len / 2 } else { len / 2 };
+        let mean = (self.sum + half) / len;
+        self.reset();
+
+        Some(mean as i32)
+    }
+
+    /// Drops the measurements added so far.
+    pub fn reset(&mut self) {
+        self.count = 0;
+        self.sum = 0;
+    }
+}
+
+/// Splits millidegrees Celsius into whole degrees and the thousandths.
+///
+/// For display, e.g. `-1250` gives `(-1, 250)`, to be printed as `-1.250`.
+/// Temperatures between -1 °C and 0 °C give `(0, ..)`, so the sign must be
+/// taken from the millidegrees.
+pub fn split_millidegrees(millidegrees: i32) -> (i32, u32) {
+    (millidegrees / 1000, (millidegrees % 1000).abs() as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn average() {
+        let mut average = Average::new(4);
+        assert_eq!(average.push(20_000), None);
+        assert_eq!(average.push(20_250), None);
+        assert_eq!(average.push(20_250), None);
+        assert_eq!(average.push(20_500), Some(20_250));
+
+        // Starts over after each mean
+        assert_eq!(average.push(-250), None);
+        average.reset();
+        assert_eq!(average.push(1_000), None);
+
+        // At least one measurement
+        assert_eq!(Average::new(0).samples(), 1);
+        assert_eq!(Average::new(0).push(-750), Some(-750));
+    }
+
+    #[test]
+    fn average_negative() {
+        let mut average = Average::new(3);
+        average.push(-1_000);
+        average.push(-1_250);
+        assert_eq!(average.push(-1_250), Some(-1_167));
+
+        let mut average = Average::new(2);
+        average.push(250);
+        assert_eq!(average.push(-1_000), Some(-375));
+    }
+
+    fn mean(measurements: &[i32]) -> Option<i32> {
+        let mut average = Average::new(measurements.len() as u32);
+        measurements.iter().filter_map(|m| average.push(*m)).next()
+    }
+
+    #[test]
+    fn average_rounding() {
+        // Halves round away from zero
+        assert_eq!(mean(&[0, 1]), Some(1));
+        assert_eq!(mean(&[0, -1]), Some(-1));
+        assert_eq!(mean(&[-250, 0, 0, -250]), Some(-125));
+        assert_eq!(mean(&[-500, 0, 0, -2]), Some(-126));
+
+        // Others round to the nearest
+        assert_eq!(mean(&[0, 0, 0, 1]), Some(0));
+        assert_eq!(mean(&[0, 0, 1, 2]), Some(1));
+        assert_eq!(mean(&[0, 0, 0, -1]), Some(0));
+        assert_eq!(mean(&[0, 0, -1, -2]), Some(-1));
+    }
+
+    #[test]
+    fn split() {
+        assert_eq!(split_millidegrees(21_750), (21, 750));
+        assert_eq!(split_millidegrees(1_000), (1, 0));
+        assert_eq!(split_millidegrees(0), (0, 0));
+        assert_eq!(split_millidegrees(-250), (0, 250));
+        assert_eq!(split_millidegrees(-1_250), (-1, 250));
+        assert_eq!(split_millidegrees(-40_000), (-40, 0));
     }
 }
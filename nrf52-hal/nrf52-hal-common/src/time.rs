@@ -1,21 +1,39 @@
 //! Time units
+//!
+//! Rates (`Hertz`, `KiloHertz`, `MegaHertz`, `Bps`) and durations
+//! (`MicroSeconds`, `MilliSeconds`, `Seconds`). Coarser units convert into
+//! finer ones with `Into`, which saturates at the largest value of the finer
+//! unit, about 71 minutes for `MicroSeconds`; the `checked_*` methods return
+//! `None` instead. A rate converts into the period of one cycle and back.
 
 /// Bits per second
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Bps(pub u32);
 
 /// Hertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct Hertz(pub u32);
 
 /// KiloHertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct KiloHertz(pub u32);
 
 /// MegaHertz
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub struct MegaHertz(pub u32);
 
+/// Microseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MicroSeconds(pub u32);
+
+/// Milliseconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct MilliSeconds(pub u32);
+
+/// Seconds
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Seconds(pub u32);
+
 /// Extension trait that adds convenience methods to the `u32` type
 pub trait U32Ext {
     /// Wrap in `Bps`
@@ -29,6 +47,15 @@ pub trait U32Ext {
 
     /// Wrap in `MegaHertz`
     fn mhz(self) -> MegaHertz;
+
+    /// Wrap in `MicroSeconds`
+    fn us(self) -> MicroSeconds;
+
+    /// Wrap in `MilliSeconds`
+    fn ms(self) -> MilliSeconds;
+
+    /// Wrap in `Seconds`
+    fn s(self) -> Seconds;
 }
 
 impl U32Ext for u32 {
@@ -47,22 +74,275 @@ impl U32Ext for u32 {
     fn mhz(self) -> MegaHertz {
         MegaHertz(self)
     }
+
+    fn us(self) -> MicroSeconds {
+        MicroSeconds(self)
+    }
+
+    fn ms(self) -> MilliSeconds {
+        MilliSeconds(self)
+    }
+
+    fn s(self) -> Seconds {
+        Seconds(self)
+    }
 }
 
 impl Into<Hertz> for KiloHertz {
     fn into(self) -> Hertz {
-        Hertz(self.0 * 1_000)
+        Hertz(self.0.saturating_mul(1_000))
     }
 }
 
 impl Into<Hertz> for MegaHertz {
     fn into(self) -> Hertz {
-        Hertz(self.0 * 1_000_000)
+        Hertz(self.0.saturating_mul(1_000_000))
     }
 }
 
 impl Into<KiloHertz> for MegaHertz {
     fn into(self) -> KiloHertz {
-        KiloHertz(self.0 * 1_000)
+        KiloHertz(self.0.saturating_mul(1_000))
+    }
+}
+
+impl Into<MicroSeconds> for MilliSeconds {
+    fn into(self) -> MicroSeconds {
+        MicroSeconds(self.0.saturating_mul(1_000))
+    }
+}
+
+impl Into<MicroSeconds> for Seconds {
+    fn into(self) -> MicroSeconds {
+        MicroSeconds(self.0.saturating_mul(1_000_000))
+    }
+}
+
+impl Into<MilliSeconds> for Seconds {
+    fn into(self) -> MilliSeconds {
+        MilliSeconds(self.0.saturating_mul(1_000))
+    }
+}
+
+macro_rules! checked_arithmetic {
+    ($($unit:ident,)+) => {
+        $(
+            impl $unit {
+                pub fn checked_add(self, rhs: Self) -> Option<Self> {
+                    self.0.checked_add(rhs.0).map($unit)
+                }
+
+                pub fn checked_sub(self, rhs: Self) -> Option<Self> {
+                    self.0.checked_sub(rhs.0).map($unit)
+                }
+
+                pub fn checked_mul(self, rhs: u32) -> Option<Self> {
+                    self.0.checked_mul(rhs).map($unit)
+                }
+
+                pub fn checked_div(self, rhs: u32) -> Option<Self> {
+                    self.0.checked_div(rhs).map($unit)
+                }
+            }
+        )+
+    }
+}
+
+checked_arithmetic!(
+    Bps,
+    Hertz,
+    KiloHertz,
+    MegaHertz,
+    MicroSeconds,
+    MilliSeconds,
+    Seconds,
+);
+
+impl KiloHertz {
+    /// Convert to `Hertz`, `None` on overflow
+    pub fn checked_hz(self) -> Option<Hertz> {
+        self.0.checked_mul(1_000).map(Hertz)
+    }
+}
+
+impl MegaHertz {
+    /// Convert to `Hertz`, `None` on overflow
+    pub fn checked_hz(self) -> Option<Hertz> {
+        self.0.checked_mul(1_000_000).map(Hertz)
+    }
+}
+
+impl MilliSeconds {
+    /// Convert to `MicroSeconds`, `None` on overflow
+    pub fn checked_us(self) -> Option<MicroSeconds> {
+        self.0.checked_mul(1_000).map(MicroSeconds)
+    }
+}
+
+impl Seconds {
+    /// Convert to `MicroSeconds`, `None` on overflow
+    pub fn checked_us(self) -> Option<MicroSeconds> {
+        self.0.checked_mul(1_000_000).map(MicroSeconds)
+    }
+
+    /// Convert to `MilliSeconds`, `None` on overflow
+    pub fn checked_ms(self) -> Option<MilliSeconds> {
+        self.0.checked_mul(1_000).map(MilliSeconds)
+    }
+}
+
+impl Hertz {
+    /// The period of one cycle, rounded to the nearest microsecond
+    ///
+    /// Returns `None` for 0 Hz.
+    pub fn period(self) -> Option<MicroSeconds> {
+        if self.0 == 0 {
+            return None;
+        }
+
+        Some(MicroSeconds((1_000_000 + self.0 / 2) / self.0))
+    }
+
+    /// Number of cycles in `duration`, `None` on overflow
+    pub fn cycles<D: Into<MicroSeconds>>(self, duration: D) -> Option<u32> {
+        let cycles = u64::from(self.0) * u64::from(duration.into().0) / 1_000_000;
+        if cycles > u64::from(u32::max_value()) {
+            None
+        } else {
+            Some(cycles as u32)
+        }
+    }
+}
+
+impl MicroSeconds {
+    /// The rate of a cycle of this period, rounded to the nearest Hertz
+    ///
+    /// Returns `None` for a period of 0.
+    pub fn rate(self) -> Option<Hertz> {
+        if self.0 == 0 {
+            return None;
+        }
+
+        Some(Hertz((1_000_000 + self.0 / 2) / self.0))
+    }
+}
+
+/// A bus frequency of a peripheral, either one of the frequencies `F` it
+/// supports or a rate
+///
+/// Rates select the highest supported frequency not above the rate.
+pub trait IntoFrequency<F> {
+    fn into_frequency(self) -> F;
+}
+
+impl<F> IntoFrequency<F> for KiloHertz
+where
+    Hertz: IntoFrequency<F>,
+{
+    fn into_frequency(self) -> F {
+        let hz: Hertz = self.into();
+        hz.into_frequency()
+    }
+}
+
+impl<F> IntoFrequency<F> for MegaHertz
+where
+    Hertz: IntoFrequency<F>,
+{
+    fn into_frequency(self) -> F {
+        let hz: Hertz = self.into();
+        hz.into_frequency()
+    }
+}
+
+/// Index of the highest of the ascending `rates` not above `rate`, or of
+/// the lowest one if all are above
+pub(crate) fn highest_not_above(rate: u32, rates: &[u32]) -> usize {
+    rates.iter().rposition(|&r| r <= rate).unwrap_or(0)
+}
+
+/// Index of the entry of `rates` closest to `rate`
+pub(crate) fn closest(rate: u32, rates: &[u32]) -> usize {
+    let distance = |r: u32| if r > rate { r - rate } else { rate - r };
+    let mut best = 0;
+    for (i, &r) in rates.iter().enumerate() {
+        if distance(r) < distance(rates[best]) {
+            best = i;
+        }
+    }
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions() {
+        let hz: Hertz = 125.khz().into();
+        assert_eq!(hz, 125_000.hz());
+        let hz: Hertz = 64.mhz().into();
+        assert_eq!(hz, 64_000_000.hz());
+        let khz: KiloHertz = 8.mhz().into();
+        assert_eq!(khz, 8_000.khz());
+
+        let us: MicroSeconds = 1_500.ms().into();
+        assert_eq!(us, 1_500_000.us());
+        let us: MicroSeconds = 4_294.s().into();
+        assert_eq!(us, 4_294_000_000.us());
+        let ms: MilliSeconds = 90.s().into();
+        assert_eq!(ms, 90_000.ms());
+    }
+
+    #[test]
+    fn conversions_saturate() {
+        let hz: Hertz = 4_295.mhz().into();
+        assert_eq!(hz, Hertz(u32::max_value()));
+        let us: MicroSeconds = 4_295.s().into();
+        assert_eq!(us, MicroSeconds(u32::max_value()));
+        let us: MicroSeconds = 4_294_968.ms().into();
+        assert_eq!(us, MicroSeconds(u32::max_value()));
+        let ms: MilliSeconds = 4_294_968.s().into();
+        assert_eq!(ms, MilliSeconds(u32::max_value()));
+
+        assert_eq!(4_294.s().checked_us(), Some(4_294_000_000.us()));
+        assert_eq!(4_295.s().checked_us(), None);
+        assert_eq!(4_294_968.ms().checked_us(), None);
+        assert_eq!(4_294_968.s().checked_ms(), None);
+        assert_eq!(4_295.mhz().checked_hz(), None);
+    }
+
+    #[test]
+    fn period_and_rate() {
+        assert_eq!(1.khz().checked_hz().unwrap().period(), Some(1_000.us()));
+        assert_eq!(3.hz().period(), Some(333_333.us()));
+        assert_eq!(0.hz().period(), None);
+        assert_eq!(20.us().rate(), Some(50_000.hz()));
+        assert_eq!(0.us().rate(), None);
+        assert_eq!(1.mhz().checked_hz().unwrap().cycles(2.ms()), Some(2_000));
+        assert_eq!(64_000_000.hz().cycles(68.s()), None);
+    }
+
+    #[test]
+    fn highest_not_above_rate() {
+        let rates = [100, 250, 400];
+        assert_eq!(highest_not_above(50, &rates), 0);
+        assert_eq!(highest_not_above(100, &rates), 0);
+        assert_eq!(highest_not_above(249, &rates), 0);
+        assert_eq!(highest_not_above(250, &rates), 1);
+        assert_eq!(highest_not_above(399, &rates), 1);
+        assert_eq!(highest_not_above(1_000, &rates), 2);
+    }
+
+    #[test]
+    fn closest_rate() {
+        let rates = [1_200, 2_400, 4_800, 9_600];
+        assert_eq!(closest(0, &rates), 0);
+        assert_eq!(closest(2_000, &rates), 1);
+        assert_eq!(closest(3_599, &rates), 1);
+        // Ties go to the lower rate
+        assert_eq!(closest(3_600, &rates), 1);
+        assert_eq!(closest(3_601, &rates), 2);
+        assert_eq!(closest(u32::max_value(), &rates), 3);
     }
 }
@@ -1,10 +1,17 @@
 //! HAL interface to the TIMER peripheral
 //!
 //! See product specification, chapter 24.
+//!
+//! `TimerExt::constrain` gives a one-shot timer at 1 MHz with 32 bits, as
+//! used for delays. `TimerExt::builder` selects the frequency, bit mode and
+//! counter mode, periodic operation, and hands out the remaining
+//! capture/compare registers.
 
+use core::marker::PhantomData;
 use core::ops::Deref;
 
 use crate::target::{timer0, Interrupt, NVIC, TIMER0, TIMER1, TIMER2};
+use crate::time::{Hertz, MicroSeconds};
 use embedded_hal::{prelude::*, timer};
 use nb::{self, block};
 use void::{unreachable, Void};
@@ -12,67 +19,238 @@ use void::{unreachable, Void};
 #[cfg(any(feature = "52832", feature = "52840"))]
 use crate::target::{TIMER3, TIMER4};
 
+pub use crate::target::timer0::bitmode::BITMODEW as BitMode;
+
+/// Frequency of the clock feeding the prescaler
+const BASE_FREQ: u32 = 16_000_000;
+
+/// Largest prescaler value, dividing the clock by 2^9
+const MAX_PRESCALER: u8 = 9;
+
 pub trait TimerExt: Deref<Target = timer0::RegisterBlock> + Sized {
     // The interrupt that belongs to this timer instance
     const INTERRUPT: Interrupt;
 
+    /// Number of capture/compare registers of this timer instance
+    const NUM_CC: usize;
+
+    fn ptr() -> *const timer0::RegisterBlock;
+
+    /// A one-shot timer at 1 MHz with 32 bits
     fn constrain(self) -> Timer<Self>;
+
+    /// Configure the timer, starting from the settings of `constrain`
+    fn builder(self) -> TimerBuilder<Self>;
 }
 
 macro_rules! impl_timer_ext {
-    ($($timer:tt,)*) => {
+    ($($timer:tt: $num_cc:expr,)*) => {
         $(
             impl TimerExt for $timer {
                 const INTERRUPT: Interrupt = Interrupt::$timer;
 
+                const NUM_CC: usize = $num_cc;
+
+                fn ptr() -> *const timer0::RegisterBlock {
+                    $timer::ptr()
+                }
+
                 fn constrain(self) -> Timer<Self> {
                     Timer::new(self)
                 }
+
+                fn builder(self) -> TimerBuilder<Self> {
+                    TimerBuilder::new(self)
+                }
             }
         )*
     }
 }
 
-impl_timer_ext!(TIMER0, TIMER1, TIMER2,);
+impl_timer_ext!(TIMER0: 4, TIMER1: 4, TIMER2: 4,);
 
 #[cfg(any(feature = "52832", feature = "52840"))]
-impl_timer_ext!(TIMER3, TIMER4,);
+impl_timer_ext!(TIMER3: 6, TIMER4: 6,);
 
-/// Interface to a TIMER instance
-///
-/// Right now, this is a very basic interface. The timer will always be
-/// hardcoded to a frequency of 1 MHz and 32 bits accuracy.
-pub struct Timer<T>(T);
+// Zero Size Type State structs
+
+/// The timer stops when it reaches the value given to `start`
+pub struct OneShot;
+/// The timer restarts from zero when it reaches the value given to `start`
+pub struct Periodic;
+
+/// Timer configuration, obtained with `TimerExt::builder`
+pub struct TimerBuilder<T, MODE = OneShot> {
+    timer: T,
+    prescaler: u8,
+    bit_mode: BitMode,
+    counter: bool,
+    _mode: PhantomData<MODE>,
+}
 
-impl<T> Timer<T>
+impl<T> TimerBuilder<T, OneShot>
 where
     T: TimerExt,
 {
     fn new(timer: T) -> Self {
+        TimerBuilder {
+            timer,
+            prescaler: 4, // 1 MHz
+            bit_mode: BitMode::_32BIT,
+            counter: false,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Restart from zero, instead of stopping, when reaching the value
+    /// given to `start`
+    pub fn periodic(self) -> TimerBuilder<T, Periodic> {
+        TimerBuilder {
+            timer: self.timer,
+            prescaler: self.prescaler,
+            bit_mode: self.bit_mode,
+            counter: self.counter,
+            _mode: PhantomData,
+        }
+    }
+}
+
+impl<T, MODE> TimerBuilder<T, MODE>
+where
+    T: TimerExt,
+{
+    /// Count at `frequency`, rounded down to 16 MHz divided by a power of two
+    ///
+    /// Frequencies below 31250 Hz select the slowest rate, 31250 Hz. The
+    /// selected frequency is returned by `Timer::frequency`.
+    pub fn frequency<F: Into<Hertz>>(mut self, frequency: F) -> Self {
+        let frequency = frequency.into().0;
+        let mut prescaler = 0;
+        while prescaler < MAX_PRESCALER && BASE_FREQ >> prescaler > frequency {
+            prescaler += 1;
+        }
+        self.prescaler = prescaler;
+        self
+    }
+
+    /// Divide the 16 MHz clock by 2^`prescaler`, up to 2^9
+    pub fn prescaler(mut self, prescaler: u8) -> Self {
+        self.prescaler = prescaler.min(MAX_PRESCALER);
+        self
+    }
+
+    /// Width of the counter
+    pub fn bit_mode(mut self, bit_mode: BitMode) -> Self {
+        self.bit_mode = bit_mode;
+        self
+    }
+
+    /// Count COUNT tasks, typically triggered through PPI, instead of clock
+    /// ticks
+    pub fn counter(mut self) -> Self {
+        self.counter = true;
+        self
+    }
+
+    fn configure(self) -> T {
+        let TimerBuilder {
+            timer,
+            prescaler,
+            bit_mode,
+            counter,
+            ..
+        } = self;
+
+        timer.tasks_stop.write(|w| unsafe { w.bits(1) });
+        timer.tasks_clear.write(|w| unsafe { w.bits(1) });
+        if counter {
+            timer.mode.write(|w| w.mode().counter());
+        } else {
+            timer.mode.write(|w| w.mode().timer());
+        }
+        timer
+            .prescaler
+            .write(|w| unsafe { w.prescaler().bits(prescaler) });
+        timer.bitmode.write(|w| w.bitmode().variant(bit_mode));
+
+        timer
+    }
+}
+
+impl<T> TimerBuilder<T, OneShot>
+where
+    T: TimerExt,
+{
+    /// Create the timer
+    pub fn build(self) -> Timer<T, OneShot> {
+        let timer = self.configure();
         timer
             .shorts
             .write(|w| w.compare0_clear().enabled().compare0_stop().enabled());
-        timer.prescaler.write(
-            |w| unsafe { w.prescaler().bits(4) }, // 1 MHz
-        );
-        timer.bitmode.write(|w| w.bitmode()._32bit());
 
-        Timer(timer)
+        Timer(timer, PhantomData)
     }
 
+    /// Create the timer, together with its other capture/compare registers
+    pub fn split(self) -> (Timer<T, OneShot>, Channels<T>) {
+        (self.build(), Channels::new())
+    }
+}
+
+impl<T> TimerBuilder<T, Periodic>
+where
+    T: TimerExt,
+{
+    /// Create the timer
+    pub fn build(self) -> Timer<T, Periodic> {
+        let timer = self.configure();
+        timer.shorts.write(|w| w.compare0_clear().enabled());
+
+        Timer(timer, PhantomData)
+    }
+
+    /// Create the timer, together with its other capture/compare registers
+    pub fn split(self) -> (Timer<T, Periodic>, Channels<T>) {
+        (self.build(), Channels::new())
+    }
+}
+
+/// Interface to a TIMER instance
+///
+/// The timer counts up to the value given to `start`, using capture/compare
+/// register 0, and then stops (`OneShot`) or restarts (`Periodic`). The
+/// other capture/compare registers are available as `CaptureCompare` handles
+/// from `TimerBuilder::split`.
+pub struct Timer<T, MODE = OneShot>(T, PhantomData<MODE>);
+
+impl<T> Timer<T, OneShot>
+where
+    T: TimerExt,
+{
+    fn new(timer: T) -> Self {
+        TimerBuilder::new(timer).build()
+    }
+}
+
+impl<T, MODE> Timer<T, MODE>
+where
+    T: TimerExt,
+{
     /// Return the raw interface to the underlying timer peripheral
     pub fn free(self) -> T {
         self.0
     }
 
+    /// The frequency the timer counts at, in timer mode
+    pub fn frequency(&self) -> Hertz {
+        Hertz(BASE_FREQ >> self.0.prescaler.read().prescaler().bits())
+    }
+
     /// Enables the interrupt for this timer
     ///
     /// Enables an interrupt that is fired when the timer reaches the value that
     /// is given as an argument to `start`.
     pub fn enable_interrupt(&mut self, nvic: &mut NVIC) {
-        // As of this writing, the timer code only uses
-        // `cc[0]`/`events_compare[0]`. If the code is extended to use other
-        // compare registers, the following needs to be adapted.
         self.0.intenset.modify(|_, w| w.compare0().set());
 
         nvic.enable(T::INTERRUPT);
@@ -81,51 +259,94 @@ where
     /// Disables the interrupt for this timer
     ///
     /// Disables an interrupt that is fired when the timer reaches the value
-    /// that is given as an argument to `start`.
+    /// that is given as an argument to `start`. The interrupts of other
+    /// capture/compare registers are left enabled.
     pub fn disable_interrupt(&mut self, nvic: &mut NVIC) {
-        // As of this writing, the timer code only uses
-        // `cc[0]`/`events_compare[0]`. If the code is extended to use other
-        // compare registers, the following needs to be adapted.
         self.0.intenclr.modify(|_, w| w.compare0().clear());
 
-        nvic.disable(T::INTERRUPT);
+        if self.0.intenset.read().bits() == 0 {
+            nvic.disable(T::INTERRUPT);
+        }
     }
 
+    /// Start the timer for the given number of cycles, or COUNT tasks in
+    /// counter mode
+    ///
+    /// `cycles` must fit in the bit mode.
+    pub fn start_cycles(&mut self, cycles: u32) {
+        // Configure timer to trigger EVENTS_COMPARE when given number of cycles
+        // is reached.
+        self.0.cc[0].write(|w|
+            // Values wider than the bit mode never trigger the event
+            unsafe { w.cc().bits(cycles) });
+
+        // Clear the counter value
+        self.0.tasks_clear.write(|w| unsafe { w.bits(1) });
+
+        // Start the timer
+        self.0.tasks_start.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Block for the given number of cycles
     pub fn delay(&mut self, cycles: u32) {
-        self.start(cycles);
+        self.start_cycles(cycles);
         match block!(self.wait()) {
             Ok(_) => {}
             Err(x) => unreachable(x),
         }
     }
+
+    /// The COMPARE event of capture/compare register 0, fired when the
+    /// value given to `start` is reached, for use as a PPI event endpoint
+    ///
+    /// The events of the other registers are available from their
+    /// `CaptureCompare` handles.
+    pub fn event_compare(&self) -> &timer0::EVENTS_COMPARE {
+        &self.0.events_compare[0]
+    }
+
+    /// The START task, for use as a PPI task endpoint
+    pub fn task_start(&self) -> &timer0::TASKS_START {
+        &self.0.tasks_start
+    }
+
+    /// The STOP task, for use as a PPI task endpoint
+    pub fn task_stop(&self) -> &timer0::TASKS_STOP {
+        &self.0.tasks_stop
+    }
+
+    /// The CLEAR task, for use as a PPI task endpoint
+    pub fn task_clear(&self) -> &timer0::TASKS_CLEAR {
+        &self.0.tasks_clear
+    }
+
+    /// The COUNT task (counter mode only), for use as a PPI task endpoint
+    pub fn task_count(&self) -> &timer0::TASKS_COUNT {
+        &self.0.tasks_count
+    }
 }
 
-impl<T> timer::CountDown for Timer<T>
+impl<T, MODE> timer::CountDown for Timer<T, MODE>
 where
     T: TimerExt,
 {
-    type Time = u32;
+    type Time = MicroSeconds;
 
     /// Start the timer
     ///
-    /// The timer will run for the given number of cycles, then it will stop and
-    /// reset.
-    fn start<Time>(&mut self, cycles: Time)
+    /// The timer will run for the given duration, then it will stop and
+    /// reset, or only reset if periodic. The duration is converted to cycles
+    /// at the timer frequency, saturating at the largest cycle count, and
+    /// must fit in the bit mode. In counter mode, use `start_cycles` instead.
+    fn start<Time>(&mut self, duration: Time)
     where
         Time: Into<Self::Time>,
     {
-        // Configure timer to trigger EVENTS_COMPARE when given number of cycles
-        // is reached.
-        self.0.cc[0].write(|w|
-            // The timer mode was set to 32 bits above, so all possible values
-            // of `cycles` are valid.
-            unsafe { w.cc().bits(cycles.into()) });
-
-        // Clear the counter value
-        self.0.tasks_clear.write(|w| unsafe { w.bits(1) });
-
-        // Start the timer
-        self.0.tasks_start.write(|w| unsafe { w.bits(1) });
+        let cycles = self
+            .frequency()
+            .cycles(duration.into())
+            .unwrap_or(u32::max_value());
+        self.start_cycles(cycles);
     }
 
     /// Wait for the timer to stop
@@ -148,3 +369,147 @@ where
         Ok(())
     }
 }
+
+impl<T> timer::Periodic for Timer<T, Periodic> where T: TimerExt {}
+
+impl<T, MODE> timer::Cancel for Timer<T, MODE>
+where
+    T: TimerExt,
+{
+    type Error = Void;
+
+    /// Stop the timer and reset its counter
+    fn cancel(&mut self) -> Result<(), Void> {
+        self.0.tasks_stop.write(|w| unsafe { w.bits(1) });
+        self.0.tasks_clear.write(|w| unsafe { w.bits(1) });
+        self.0.events_compare[0].reset();
+
+        Ok(())
+    }
+}
+
+/// The capture/compare registers of a timer other than register 0
+///
+/// TIMER0 to TIMER2 have registers 1 to 3, TIMER3 and TIMER4 also have
+/// registers 4 and 5.
+pub struct Channels<T> {
+    pub cc1: CaptureCompare<T>,
+    pub cc2: CaptureCompare<T>,
+    pub cc3: CaptureCompare<T>,
+    pub cc4: Option<CaptureCompare<T>>,
+    pub cc5: Option<CaptureCompare<T>>,
+}
+
+impl<T> Channels<T>
+where
+    T: TimerExt,
+{
+    fn new() -> Self {
+        let cc = |index| CaptureCompare {
+            index,
+            _timer: PhantomData,
+        };
+
+        Channels {
+            cc1: cc(1),
+            cc2: cc(2),
+            cc3: cc(3),
+            cc4: if T::NUM_CC > 4 { Some(cc(4)) } else { None },
+            cc5: if T::NUM_CC > 5 { Some(cc(5)) } else { None },
+        }
+    }
+}
+
+/// A capture/compare register of a timer
+pub struct CaptureCompare<T> {
+    index: usize,
+    _timer: PhantomData<T>,
+}
+
+impl<T> CaptureCompare<T>
+where
+    T: TimerExt,
+{
+    fn regs(&self) -> &timer0::RegisterBlock {
+        // NOTE(unsafe) the handle owns its CC register, event and task, and
+        // only sets or clears its own bits in the shared INTEN and SHORTS
+        unsafe { &*T::ptr() }
+    }
+
+    /// Number of the register
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    /// Set the value the COMPARE event fires at
+    pub fn set_compare(&mut self, value: u32) {
+        self.regs().cc[self.index].write(|w| unsafe { w.bits(value) });
+    }
+
+    /// Copy the counter into the register and return it
+    pub fn capture(&mut self) -> u32 {
+        self.regs().tasks_capture[self.index].write(|w| unsafe { w.bits(1) });
+        self.value()
+    }
+
+    /// The value of the register, the compare value or the last capture
+    pub fn value(&self) -> u32 {
+        self.regs().cc[self.index].read().bits()
+    }
+
+    /// Has the COMPARE event fired? The event is cleared.
+    pub fn is_triggered(&mut self) -> bool {
+        let event = &self.regs().events_compare[self.index];
+        let triggered = event.read().bits() != 0;
+        if triggered {
+            event.reset();
+        }
+        triggered
+    }
+
+    /// Clear the counter when the COMPARE event fires
+    pub fn clear_on_compare(&mut self, enable: bool) {
+        self.set_short(self.index, enable);
+    }
+
+    /// Stop the timer when the COMPARE event fires
+    pub fn stop_on_compare(&mut self, enable: bool) {
+        self.set_short(self.index + 8, enable);
+    }
+
+    fn set_short(&mut self, bit: usize, enable: bool) {
+        self.regs().shorts.modify(|r, w| unsafe {
+            if enable {
+                w.bits(r.bits() | 1 << bit)
+            } else {
+                w.bits(r.bits() & !(1 << bit))
+            }
+        });
+    }
+
+    /// Enable the interrupt of the COMPARE event
+    ///
+    /// The interrupt of the timer still needs to be enabled in the NVIC.
+    pub fn enable_interrupt(&mut self) {
+        self.regs()
+            .intenset
+            .write(|w| unsafe { w.bits(1 << (16 + self.index)) });
+    }
+
+    /// Disable the interrupt of the COMPARE event
+    pub fn disable_interrupt(&mut self) {
+        self.regs()
+            .intenclr
+            .write(|w| unsafe { w.bits(1 << (16 + self.index)) });
+    }
+
+    /// The COMPARE event, for use as a PPI event endpoint
+    pub fn event_compare(&self) -> &timer0::EVENTS_COMPARE {
+        &self.regs().events_compare[self.index]
+    }
+
+    /// The CAPTURE task, for use as a PPI task endpoint
+    pub fn task_capture(&self) -> &timer0::TASKS_CAPTURE {
+        &self.regs().tasks_capture[self.index]
+    }
+}
@@ -16,17 +16,37 @@ use crate::gpio::{Floating, Input, Pin};
 
 use crate::target_constants::EASY_DMA_SIZE;
 
+use crate::time::{self, Hertz};
+
+pub use crate::time::IntoFrequency;
+
 pub use crate::target::twim0::frequency::FREQUENCYW as Frequency;
 
+impl IntoFrequency<Frequency> for Frequency {
+    fn into_frequency(self) -> Frequency {
+        self
+    }
+}
+
+impl IntoFrequency<Frequency> for Hertz {
+    fn into_frequency(self) -> Frequency {
+        match time::highest_not_above(self.0, &[100_000, 250_000, 400_000]) {
+            0 => Frequency::K100,
+            1 => Frequency::K250,
+            _ => Frequency::K400,
+        }
+    }
+}
+
 pub trait TwimExt: Deref<Target = twim0::RegisterBlock> + Sized {
-    fn constrain(self, pins: Pins, frequency: Frequency) -> Twim<Self>;
+    fn constrain<F: IntoFrequency<Frequency>>(self, pins: Pins, frequency: F) -> Twim<Self>;
 }
 
 macro_rules! impl_twim_ext {
     ($($twim:ty,)*) => {
         $(
             impl TwimExt for $twim {
-                fn constrain(self, pins: Pins, frequency: Frequency)
+                fn constrain<F: IntoFrequency<Frequency>>(self, pins: Pins, frequency: F)
                     -> Twim<Self>
                 {
                     Twim::new(self, pins, frequency)
@@ -56,7 +76,9 @@ impl<T> Twim<T>
 where
     T: TwimExt,
 {
-    pub fn new(twim: T, pins: Pins, frequency: Frequency) -> Self {
+    pub fn new<F: IntoFrequency<Frequency>>(twim: T, pins: Pins, frequency: F) -> Self {
+        let frequency = frequency.into_frequency();
+
         // The TWIM peripheral requires the pins to be in a mode that is not
         // exposed through the GPIO API, and might it might not make sense to
         // expose it there.
@@ -10,36 +10,94 @@ use core::sync::atomic::{compiler_fence, Ordering::SeqCst};
 
 use crate::target::{uarte0, UARTE0};
 
+#[cfg(feature = "52840")]
+use crate::target::UARTE1;
+
+use crate::framing;
 use crate::gpio::{Floating, Input, Output, Pin, PushPull};
+use crate::ppi;
 use crate::prelude::*;
 use crate::target_constants::EASY_DMA_SIZE;
-use crate::timer::Timer;
+use crate::time::{self, Bps};
+use crate::timer::{Timer, TimerExt};
 
 use heapless::{
     consts::*,
     pool,
     pool::singleton::{Box, Pool},
     spsc::{Consumer, Queue},
+    ArrayLength,
 };
 
 // Re-export SVD variants to allow user to directly set values
 pub use crate::target::uarte0::{baudrate::BAUDRATEW as Baudrate, config::PARITYW as Parity};
 
+/// A baud rate, either a `Baudrate` or a rate in `Bps`
+///
+/// Rates select the closest supported baud rate.
+pub trait IntoBaudrate {
+    fn into_baudrate(self) -> Baudrate;
+}
+
+impl IntoBaudrate for Baudrate {
+    fn into_baudrate(self) -> Baudrate {
+        self
+    }
+}
+
+impl IntoBaudrate for Bps {
+    fn into_baudrate(self) -> Baudrate {
+        let rates = [
+            1_200, 2_400, 4_800, 9_600, 14_400, 19_200, 28_800, 38_400, 57_600, 76_800,
+            115_200, 230_400, 250_000, 460_800, 921_600, 1_000_000,
+        ];
+        match time::closest(self.0, &rates) {
+            0 => Baudrate::BAUD1200,
+            1 => Baudrate::BAUD2400,
+            2 => Baudrate::BAUD4800,
+            3 => Baudrate::BAUD9600,
+            4 => Baudrate::BAUD14400,
+            5 => Baudrate::BAUD19200,
+            6 => Baudrate::BAUD28800,
+            7 => Baudrate::BAUD38400,
+            8 => Baudrate::BAUD57600,
+            9 => Baudrate::BAUD76800,
+            10 => Baudrate::BAUD115200,
+            11 => Baudrate::BAUD230400,
+            12 => Baudrate::BAUD250000,
+            13 => Baudrate::BAUD460800,
+            14 => Baudrate::BAUD921600,
+            _ => Baudrate::BAUD1M,
+        }
+    }
+}
+
 pub trait UarteExt: Deref<Target = uarte0::RegisterBlock> + Sized {
-    fn constrain(self, pins: Pins, parity: Parity, baudrate: Baudrate) -> Uarte<Self>;
+    fn constrain<B: IntoBaudrate>(self, pins: Pins, parity: Parity, baudrate: B) -> Uarte<Self>;
     fn ptr() -> *const uarte0::RegisterBlock;
 }
 
-impl UarteExt for UARTE0 {
-    fn constrain(self, pins: Pins, parity: Parity, baudrate: Baudrate) -> Uarte<Self> {
-        Uarte::new(self, pins, parity, baudrate)
-    }
+macro_rules! impl_uarte_ext {
+    ($($uarte:ty,)*) => {
+        $(
+            impl UarteExt for $uarte {
+                fn constrain<B: IntoBaudrate>(self, pins: Pins, parity: Parity, baudrate: B) -> Uarte<Self> {
+                    Uarte::new(self, pins, parity, baudrate)
+                }
 
-    fn ptr() -> *const uarte0::RegisterBlock {
-        UARTE0::ptr()
+                fn ptr() -> *const uarte0::RegisterBlock {
+                    <$uarte>::ptr()
+                }
+            }
+        )*
     }
 }
 
+impl_uarte_ext!(UARTE0,);
+
+#[cfg(feature = "52840")]
+impl_uarte_ext!(UARTE1,);
+
 /// Interface to a UARTE instance
 ///
 /// This is a very basic interface that comes with the following limitations:
@@ -54,7 +112,9 @@ impl<T> Uarte<T>
 where
     T: UarteExt,
 {
-    pub fn new(uarte: T, mut pins: Pins, parity: Parity, baudrate: Baudrate) -> Self {
+    pub fn new<B: IntoBaudrate>(uarte: T, mut pins: Pins, parity: Parity, baudrate: B) -> Self {
+        let baudrate = baudrate.into_baudrate();
+
         // Select pins
         uarte.psel.rxd.write(|w| {
             let w = unsafe { w.pin().bits(pins.rxd.pin) };
@@ -213,7 +273,7 @@ where
         self.start_read(rx_buffer)?;
 
         // Start the timeout timer
-        timer.start(cycles);
+        timer.start_cycles(cycles);
 
         // Wait for transmission to end
         let mut event_complete = false;
@@ -299,55 +359,362 @@ where
         self.0
     }
 
-    pub fn split(
+    /// The RXDRDY event, for use as a PPI event endpoint
+    pub fn event_rxdrdy(&self) -> &uarte0::EVENTS_RXDRDY {
+        &self.0.events_rxdrdy
+    }
+
+    /// The ENDRX event, for use as a PPI event endpoint
+    pub fn event_endrx(&self) -> &uarte0::EVENTS_ENDRX {
+        &self.0.events_endrx
+    }
+
+    /// The RXSTARTED event, for use as a PPI event endpoint
+    pub fn event_rxstarted(&self) -> &uarte0::EVENTS_RXSTARTED {
+        &self.0.events_rxstarted
+    }
+
+    /// The RXTO event, for use as a PPI event endpoint
+    pub fn event_rxto(&self) -> &uarte0::EVENTS_RXTO {
+        &self.0.events_rxto
+    }
+
+    /// The TXDRDY event, for use as a PPI event endpoint
+    pub fn event_txdrdy(&self) -> &uarte0::EVENTS_TXDRDY {
+        &self.0.events_txdrdy
+    }
+
+    /// The ENDTX event, for use as a PPI event endpoint
+    pub fn event_endtx(&self) -> &uarte0::EVENTS_ENDTX {
+        &self.0.events_endtx
+    }
+
+    /// The ERROR event, for use as a PPI event endpoint
+    pub fn event_error(&self) -> &uarte0::EVENTS_ERROR {
+        &self.0.events_error
+    }
+
+    /// The STARTRX task, for use as a PPI task endpoint
+    pub fn task_startrx(&self) -> &uarte0::TASKS_STARTRX {
+        &self.0.tasks_startrx
+    }
+
+    /// The STOPRX task, for use as a PPI task endpoint
+    pub fn task_stoprx(&self) -> &uarte0::TASKS_STOPRX {
+        &self.0.tasks_stoprx
+    }
+
+    /// The STARTTX task, for use as a PPI task endpoint
+    pub fn task_starttx(&self) -> &uarte0::TASKS_STARTTX {
+        &self.0.tasks_starttx
+    }
+
+    /// The STOPTX task, for use as a PPI task endpoint
+    pub fn task_stoptx(&self) -> &uarte0::TASKS_STOPTX {
+        &self.0.tasks_stoptx
+    }
+
+    /// The FLUSHRX task, for use as a PPI task endpoint
+    pub fn task_flushrx(&self) -> &uarte0::TASKS_FLUSHRX {
+        &self.0.tasks_flushrx
+    }
+
+    /// Split the UARTE into interrupt driven receive and transmit halves
+    ///
+    /// DMA buffers are allocated from the pool `P`, whose blocks may be of
+    /// any size up to the EasyDMA limit. Received chunks are handed out by
+    /// `UarteRX::process_interrupt`, chunks to transmit are taken from the
+    /// consumer `txc`. `rxq` holds the buffers in flight, two suffice for
+    /// double buffering.
+    pub fn split<P, RN, TN>(
         self,
-        rxq: Queue<Box<DMAPool>, U2>,
-        txc: Consumer<'static, Box<DMAPool>, TXQSize>,
-    ) -> (UarteRX<T>, UarteTX<T>) {
-        let mut rx = UarteRX::<T>::new(rxq);
+        rxq: Queue<Box<P>, RN>,
+        txc: Consumer<'static, Chunk<P>, TN>,
+    ) -> (UarteRX<T, P, RN>, UarteTX<T, P, TN>)
+    where
+        P: Pool,
+        P::Data: AsRef<[u8]> + AsMut<[u8]>,
+        RN: ArrayLength<Box<P>>,
+        TN: ArrayLength<Chunk<P>>,
+    {
+        let mut rx = UarteRX::<T, P, RN>::new(rxq);
         rx.enable_interrupts();
         rx.prepare_read().unwrap();
         rx.start_read();
 
-        let tx = UarteTX::<T>::new(txc);
+        let tx = UarteTX::<T, P, TN>::new(txc);
         tx.enable_interrupts();
         (rx, tx)
     }
 }
 
-// DMA block is 4 bytes
+// Default DMA block is 4 bytes
 pub const DMA_SIZE: usize = 4;
 pool!(DMAPool: [u8; DMA_SIZE]);
 
-pub struct UarteRX<T> {
-    rxq: Queue<Box<DMAPool>, U2>, // double buffering of DMA chunkns
+/// Default depth of the receive buffer queue, double buffering
+pub type RXQSize = U2;
+
+/// Default depth of the transmit queue
+pub type TXQSize = U4;
+
+/// A DMA buffer together with the number of bytes in use
+///
+/// Dereferences to the bytes in use.
+pub struct Chunk<P>
+where
+    P: Pool,
+{
+    buf: Box<P>,
+    len: usize,
+}
+
+impl<P> Chunk<P>
+where
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+{
+    /// Use the first `len` bytes of `buf`, `len` is limited to the size of
+    /// the buffer
+    pub fn new(buf: Box<P>, len: usize) -> Self {
+        let len = len.min((*buf).as_ref().len());
+        Chunk { buf, len }
+    }
+
+    /// Use the whole of `buf`
+    pub fn full(buf: Box<P>) -> Self {
+        let len = (*buf).as_ref().len();
+        Chunk { buf, len }
+    }
+
+    /// Number of bytes in use
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Is no byte in use?
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Change the number of bytes in use, limited to the size of the buffer
+    pub fn set_len(&mut self, len: usize) {
+        self.len = len.min((*self.buf).as_ref().len());
+    }
+
+    /// The whole buffer, including the bytes not in use
+    pub fn buffer_mut(&mut self) -> &mut [u8] {
+        (*self.buf).as_mut()
+    }
+
+    /// Return the underlying buffer
+    pub fn free(self) -> Box<P> {
+        self.buf
+    }
+}
+
+impl<P> Deref for Chunk<P>
+where
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+{
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.buf).as_ref()[..self.len]
+    }
+}
+
+impl<P> fmt::Debug for Chunk<P>
+where
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Length of a DMA transaction into or from `buf`
+fn dma_len(buf: &[u8]) -> usize {
+    buf.len().min(EASY_DMA_SIZE)
+}
+
+pub struct UarteRX<T, P = DMAPool, N = RXQSize, IT = NoIdleTimeout>
+where
+    P: Pool,
+    N: ArrayLength<Box<P>>,
+{
+    rxq: Queue<Box<P>, N>, // double buffering of DMA chunks
+    restarted: bool,       // reception continued after a full buffer
+    idle: IT,              // resources of the idle timeout
     _marker: core::marker::PhantomData<T>,
 }
 
+/// The receiver has no idle timeout (type state)
+pub struct NoIdleTimeout;
+
+/// The timer and PPI channels of an idle timeout, owned by the receiver
+/// (type state)
+pub struct IdleTimeout<I, C1, C2> {
+    timer: Timer<I>,
+    rx_channel: ppi::Channel<C1>,
+    timeout_channel: ppi::Channel<C2>,
+}
+
 #[derive(Debug)]
 pub enum RXError {
     RxqOverflow,
     RxqUnderflow,
     OOM,
+    /// A received frame is malformed
+    Framing,
+    /// A received frame does not fit the frame buffer
+    FrameOverflow,
+}
+
+impl From<framing::Error> for RXError {
+    fn from(e: framing::Error) -> Self {
+        match e {
+            framing::Error::Framing => RXError::Framing,
+            framing::Error::Overflow => RXError::FrameOverflow,
+        }
+    }
 }
 
-impl<T> UarteRX<T>
+impl<T, P, N> UarteRX<T, P, N>
 where
     T: UarteExt,
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+    N: ArrayLength<Box<P>>,
 {
-    fn new(rxq: Queue<Box<DMAPool>, U2>) -> Self {
+    fn new(rxq: Queue<Box<P>, N>) -> Self {
         Self {
             rxq,
+            restarted: false,
+            idle: NoIdleTimeout,
+            _marker: core::marker::PhantomData,
+        }
+    }
+
+    /// Flush partially filled buffers when the line has been idle
+    ///
+    /// Every received byte restarts `timer` through `rx_channel`. If no byte
+    /// arrives within `cycles` timer cycles, `timeout_channel` stops the
+    /// receiver, which ends the current buffer with the bytes received so far.
+    /// Reception is restarted by `process_interrupt` once the receiver has
+    /// stopped.
+    ///
+    /// The timer and PPI channels are owned by the receiver until they are
+    /// returned by `disable_idle_timeout`.
+    pub fn enable_idle_timeout<I, C1, C2>(
+        self,
+        mut timer: Timer<I>,
+        mut rx_channel: ppi::Channel<C1>,
+        mut timeout_channel: ppi::Channel<C2>,
+        cycles: u32,
+    ) -> UarteRX<T, P, N, IdleTimeout<I, C1, C2>>
+    where
+        I: TimerExt,
+        C1: ppi::ChannelNum,
+        C2: ppi::ChannelNum,
+    {
+        let uarte = unsafe { &*T::ptr() };
+
+        // Load the timeout into the timer without leaving it running. The
+        // timer stops and clears itself when it reaches the timeout.
+        timer.start_cycles(cycles);
+        timer.task_stop().write(|w| unsafe { w.bits(1) });
+        timer.task_clear().write(|w| unsafe { w.bits(1) });
+        timer.event_compare().reset();
+
+        rx_channel.set_event_endpoint(&uarte.events_rxdrdy);
+        rx_channel.set_task_endpoint(timer.task_clear());
+        rx_channel.set_fork_task_endpoint(timer.task_start());
+        rx_channel.enable();
+
+        timeout_channel.set_event_endpoint(timer.event_compare());
+        timeout_channel.set_task_endpoint(&uarte.tasks_stoprx);
+        timeout_channel.enable();
+
+        UarteRX {
+            rxq: self.rxq,
+            restarted: self.restarted,
+            idle: IdleTimeout {
+                timer,
+                rx_channel,
+                timeout_channel,
+            },
             _marker: core::marker::PhantomData,
         }
     }
+}
 
-    // we listen to RXSTARTED and ENDRX
+impl<T, P, N, I, C1, C2> UarteRX<T, P, N, IdleTimeout<I, C1, C2>>
+where
+    T: UarteExt,
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+    N: ArrayLength<Box<P>>,
+    I: TimerExt,
+    C1: ppi::ChannelNum,
+    C2: ppi::ChannelNum,
+{
+    /// Stop flushing partially filled buffers when the line is idle
+    ///
+    /// The PPI channels are disconnected and the timer is stopped before
+    /// they are returned.
+    pub fn disable_idle_timeout(
+        self,
+    ) -> (
+        UarteRX<T, P, N>,
+        Timer<I>,
+        ppi::Channel<C1>,
+        ppi::Channel<C2>,
+    ) {
+        let IdleTimeout {
+            mut timer,
+            mut rx_channel,
+            mut timeout_channel,
+        } = self.idle;
+
+        rx_channel.disable();
+        rx_channel.clear_fork_task_endpoint();
+        timeout_channel.disable();
+
+        timer.task_stop().write(|w| unsafe { w.bits(1) });
+        timer.task_clear().write(|w| unsafe { w.bits(1) });
+        timer.event_compare().reset();
+
+        let rx = UarteRX {
+            rxq: self.rxq,
+            restarted: self.restarted,
+            idle: NoIdleTimeout,
+            _marker: core::marker::PhantomData,
+        };
+        (rx, timer, rx_channel, timeout_channel)
+    }
+}
+
+impl<T, P, N, IT> UarteRX<T, P, N, IT>
+where
+    T: UarteExt,
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+    N: ArrayLength<Box<P>>,
+{
+    // we listen to RXSTARTED, ENDRX and RXTO
     pub fn enable_interrupts(&self) {
         let uarte = unsafe { &*T::ptr() };
-        uarte
-            .inten
-            .modify(|_, w| w.endrx().set_bit().rxstarted().set_bit());
+        uarte.inten.modify(|_, w| {
+            w.endrx()
+                .set_bit()
+                .rxstarted()
+                .set_bit()
+                .rxto()
+                .set_bit()
+        });
     }
 
     /// Start a UARTE read transaction
@@ -364,18 +731,18 @@ where
     pub fn prepare_read(&mut self) -> Result<(), RXError> {
         let uarte = unsafe { &*T::ptr() };
 
-        let b = DMAPool::alloc().ok_or(RXError::OOM)?.freeze();
+        let b = P::alloc().ok_or(RXError::OOM)?.freeze();
         compiler_fence(SeqCst);
         // setup start address
         uarte
             .rxd
             .ptr
-            .write(|w| unsafe { w.ptr().bits(b.as_ptr() as u32) });
+            .write(|w| unsafe { w.ptr().bits((*b).as_ref().as_ptr() as u32) });
         // setup length
         uarte
             .rxd
             .maxcnt
-            .write(|w| unsafe { w.maxcnt().bits(b.len() as _) });
+            .write(|w| unsafe { w.maxcnt().bits(dma_len((*b).as_ref()) as _) });
 
         if self.rxq.enqueue(b).is_err() {
             Err(RXError::RxqOverflow)
@@ -384,7 +751,12 @@ where
         }
     }
 
-    pub fn process_interrupt(&mut self) -> Result<Option<Box<DMAPool>>, RXError> {
+    /// Handle the UARTE interrupt for the receiver
+    ///
+    /// Returns a chunk when a DMA buffer has been filled, or ended early by
+    /// the idle timeout. Chunks ended early are only as long as the number
+    /// of bytes received; empty chunks are not returned.
+    pub fn process_interrupt(&mut self) -> Result<Option<Chunk<P>>, RXError> {
         let uarte = unsafe { &*T::ptr() };
 
         // check if dma rx transaction has started
@@ -396,37 +768,66 @@ where
             uarte.events_rxstarted.write(|w| w);
         }
 
-        // check id dma transaction finished
+        // check id dma transaction finished, before RXTO, as AMOUNT belongs
+        // to the next transaction once reception is restarted
+        let mut chunk = None;
         if uarte.events_endrx.read().bits() == 1 {
             // our transaction has finished
             let ret_b = self.rxq.dequeue().ok_or(RXError::RxqUnderflow)?;
+            let amount = uarte.rxd.amount.read().bits() as usize;
 
             // Reset the event, otherwise it will always read `1` from now on.
             uarte.events_endrx.write(|w| w);
 
-            self.start_read();
+            // A full buffer means the receiver is still running, so continue
+            // right away. Otherwise it was stopped, and is restarted on RXTO.
+            self.restarted = amount == dma_len((*ret_b).as_ref());
+            if self.restarted {
+                self.start_read();
+            }
+
+            // nothing received, the buffer returns to the pool
+            if amount != 0 {
+                chunk = Some(Chunk::new(ret_b, amount));
+            }
+        }
+
+        // check if the receiver has been stopped by the idle timeout
+        if uarte.events_rxto.read().bits() == 1 {
+            // Reset the event, otherwise it will always read `1` from now on.
+            uarte.events_rxto.write(|w| w);
 
-            return Ok(Some(ret_b)); // ok to return, rx started will be caught later
+            // the next buffer was prepared on RXSTARTED, continue with it,
+            // unless that was already done for a buffer that the timeout
+            // found full
+            if !self.restarted {
+                self.start_read();
+            }
+            self.restarted = false;
         }
 
-        // the interrupt was not RXSTARTED or ENDRX, so no action
-        Ok(None)
+        Ok(chunk) // rx started will be caught later
     }
 }
 
-pub type TXQSize = U4;
-
-pub struct UarteTX<T> {
-    txc: Consumer<'static, Box<DMAPool>, TXQSize>, // chunks to transmit
-    current: Option<Box<DMAPool>>,
+pub struct UarteTX<T, P = DMAPool, N = TXQSize>
+where
+    P: Pool,
+    N: ArrayLength<Chunk<P>>,
+{
+    txc: Consumer<'static, Chunk<P>, N>, // chunks to transmit
+    current: Option<Chunk<P>>,
     _marker: core::marker::PhantomData<T>,
 }
 
-impl<T> UarteTX<T>
+impl<T, P, N> UarteTX<T, P, N>
 where
     T: UarteExt,
+    P: Pool,
+    P::Data: AsRef<[u8]> + AsMut<[u8]>,
+    N: ArrayLength<Chunk<P>>,
 {
-    fn new(txc: Consumer<'static, Box<DMAPool>, TXQSize>) -> Self {
+    fn new(txc: Consumer<'static, Chunk<P>, N>) -> Self {
         Self {
             txc,
             current: None,
@@ -440,7 +841,7 @@ where
         uarte.inten.modify(|_, w| w.endtx().set_bit());
     }
 
-    pub fn start_write(&mut self, b: Box<DMAPool>) {
+    pub fn start_write(&mut self, b: Chunk<P>) {
         let uarte = unsafe { &*T::ptr() };
         compiler_fence(SeqCst);
         // setup start address
@@ -452,7 +853,7 @@ where
         uarte
             .txd
             .maxcnt
-            .write(|w| unsafe { w.maxcnt().bits(b.len() as _) });
+            .write(|w| unsafe { w.maxcnt().bits(dma_len(&b) as _) });
         // Start UARTE transmit transaction
         uarte.tasks_starttx.write(|w| unsafe { w.bits(1) });
         self.current = Some(b); // drops the previous current package
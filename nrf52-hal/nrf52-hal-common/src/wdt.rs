@@ -0,0 +1,323 @@
+//! HAL interface to the WDT peripheral
+//!
+//! See product specification:
+//!
+//! - nrf52832: Section 29
+//! - nrf52840: Section 6.36
+//!
+//! The watchdog is configured while inactive, then activated with a set of
+//! reload request handles, one for each enabled RR register. Every handle
+//! must be pet before the timeout expires, so each can be given to a
+//! different task that is to be supervised. Once started, the watchdog can
+//! not be stopped or reconfigured until the next reset.
+use core::marker::PhantomData;
+use core::ops::Deref;
+
+use crate::hal::watchdog;
+use crate::target::{wdt, WDT};
+
+/// Frequency of the watchdog counter, the low frequency clock
+pub const WDT_FREQ: u32 = 32_768;
+
+/// Smallest timeout accepted by CRV, in counter ticks
+pub const MIN_TIMEOUT_TICKS: u32 = 0xF;
+
+/// Value to write to an RR register to request a reload
+const RELOAD_VALUE: u32 = 0x6E52_4635;
+
+// Zero Size Type State structs
+
+/// The watchdog has not been started yet
+pub struct Inactive;
+/// The watchdog is running, fed through `WatchdogHandle`s
+pub struct Active;
+/// The watchdog is used through the embedded-hal traits, with RR0 only
+pub struct Single;
+
+/// An extension trait for constructing the high level interface
+pub trait WdtExt: Deref<Target = wdt::RegisterBlock> + Sized {
+    /// Take control of the watchdog
+    ///
+    /// The watchdog keeps running across soft and CPU lockup resets. It is
+    /// stopped by its own timeout, power-on and brown-out, a pin reset and a
+    /// wake from System OFF. If it is already running it is returned as
+    /// `Err(Running)`, from which the handles can be recovered.
+    ///
+    /// Whether the last reset came from the watchdog is reported by
+    /// `power::Power::take_reset_reason`.
+    fn constrain(self) -> Result<Watchdog<Inactive>, Running>;
+}
+
+impl WdtExt for WDT {
+    fn constrain(self) -> Result<Watchdog<Inactive>, Running> {
+        if self.runstatus.read().bits() != 0 {
+            Err(Running { wdt: self })
+        } else {
+            Ok(Watchdog {
+                wdt: self,
+                _mode: PhantomData,
+            })
+        }
+    }
+}
+
+/// A high level interface to the WDT peripheral
+pub struct Watchdog<MODE> {
+    wdt: WDT,
+    _mode: PhantomData<MODE>,
+}
+
+/// The watchdog in `Active` mode together with its reload handles
+pub struct Parts<H> {
+    pub watchdog: Watchdog<Active>,
+    pub handles: H,
+}
+
+impl<MODE> Watchdog<MODE> {
+    /// Enable the interrupt fired on timeout
+    ///
+    /// The chip is reset two 32.768 kHz clock cycles after the interrupt
+    /// fires, which is enough to save a few words of state.
+    pub fn enable_interrupt(&mut self) {
+        self.wdt.intenset.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Disable the interrupt fired on timeout
+    pub fn disable_interrupt(&mut self) {
+        self.wdt.intenclr.write(|w| unsafe { w.bits(1) });
+    }
+
+    /// Has the watchdog timed out?
+    pub fn is_timed_out(&self) -> bool {
+        self.wdt.events_timeout.read().bits() != 0
+    }
+
+    /// The configured timeout, in 32.768 kHz ticks
+    pub fn timeout_ticks(&self) -> u32 {
+        self.wdt.crv.read().bits()
+    }
+}
+
+impl Watchdog<Inactive> {
+    /// Set the timeout in 32.768 kHz ticks
+    pub fn set_timeout_ticks(&mut self, ticks: u32) -> Result<(), Error> {
+        if ticks < MIN_TIMEOUT_TICKS {
+            return Err(Error::TimeoutOutOfRange);
+        }
+
+        self.wdt.crv.write(|w| unsafe { w.bits(ticks) });
+
+        Ok(())
+    }
+
+    /// Keep counting while the CPU sleeps (the default), or pause
+    pub fn run_during_sleep(&mut self, run: bool) {
+        self.wdt.config.modify(|_, w| w.sleep().bit(run));
+    }
+
+    /// Keep counting while the CPU is halted by the debugger, or pause (the
+    /// default)
+    pub fn run_during_debug_halt(&mut self, run: bool) {
+        self.wdt.config.modify(|_, w| w.halt().bit(run));
+    }
+
+    /// Start the watchdog, enabling one RR register per handle in `H`
+    pub fn activate<H: HandleSet>(self) -> Parts<H> {
+        self.wdt.rren.write(|w| unsafe { w.bits(H::MASK) });
+        self.wdt.tasks_start.write(|w| unsafe { w.bits(1) });
+
+        Parts {
+            watchdog: Watchdog {
+                wdt: self.wdt,
+                _mode: PhantomData,
+            },
+            handles: H::new(),
+        }
+    }
+
+    /// Use the watchdog through the embedded-hal traits, with a single
+    /// reload register
+    pub fn into_single(self) -> Watchdog<Single> {
+        Watchdog {
+            wdt: self.wdt,
+            _mode: PhantomData,
+        }
+    }
+
+    /// Destructure the high level interface
+    pub fn release(self) -> WDT {
+        self.wdt
+    }
+}
+
+impl Watchdog<Active> {
+    /// Bit mask of the RR registers that have not been pet since the last
+    /// reload
+    pub fn pending_reloads(&self) -> u8 {
+        self.wdt.reqstatus.read().bits() as u8
+    }
+}
+
+impl watchdog::WatchdogEnable for Watchdog<Single> {
+    type Time = u32;
+
+    /// Start the watchdog with a timeout in 32.768 kHz ticks
+    ///
+    /// The timeout can not be changed once the watchdog runs, so later calls
+    /// only feed it.
+    fn start<T>(&mut self, ticks: T)
+    where
+        T: Into<u32>,
+    {
+        if self.wdt.runstatus.read().bits() == 0 {
+            let ticks = ticks.into().max(MIN_TIMEOUT_TICKS);
+            self.wdt.crv.write(|w| unsafe { w.bits(ticks) });
+            self.wdt.rren.write(|w| unsafe { w.bits(1) });
+            self.wdt.tasks_start.write(|w| unsafe { w.bits(1) });
+        } else {
+            watchdog::Watchdog::feed(self);
+        }
+    }
+}
+
+impl watchdog::Watchdog for Watchdog<Single> {
+    fn feed(&mut self) {
+        self.wdt.rr[0].write(|w| unsafe { w.bits(RELOAD_VALUE) });
+    }
+}
+
+/// A watchdog that was found running at startup
+pub struct Running {
+    wdt: WDT,
+}
+
+impl Running {
+    /// Recover the handles of the running watchdog
+    ///
+    /// Fails, returning `self`, if `H` does not match the RR registers that
+    /// were enabled when the watchdog was started.
+    pub fn into_parts<H: HandleSet>(self) -> Result<Parts<H>, Self> {
+        if self.wdt.rren.read().bits() != H::MASK {
+            return Err(self);
+        }
+
+        Ok(Parts {
+            watchdog: Watchdog {
+                wdt: self.wdt,
+                _mode: PhantomData,
+            },
+            handles: H::new(),
+        })
+    }
+
+    /// Bit mask of the enabled RR registers
+    pub fn enabled_handles(&self) -> u8 {
+        self.wdt.rren.read().bits() as u8
+    }
+}
+
+/// Reload register number of a watchdog handle (type state)
+pub trait HandleNum {
+    const INDEX: usize;
+}
+
+/// A reload request handle, owning one RR register
+pub struct WatchdogHandle<H> {
+    _handle: PhantomData<H>,
+}
+
+impl<H> WatchdogHandle<H>
+where
+    H: HandleNum,
+{
+    /// Request a reload through this handle's RR register
+    ///
+    /// The watchdog is reloaded once all handles have been pet.
+    pub fn pet(&mut self) {
+        // NOTE(unsafe) the handle owns its RR register, a write-only register
+        unsafe { &*WDT::ptr() }.rr[H::INDEX].write(|w| unsafe { w.bits(RELOAD_VALUE) });
+    }
+
+    /// Has this handle been pet since the last reload?
+    pub fn is_pet(&self) -> bool {
+        let reqstatus = unsafe { &*WDT::ptr() }.reqstatus.read().bits();
+        reqstatus & (1 << H::INDEX) == 0
+    }
+}
+
+impl<H> watchdog::Watchdog for WatchdogHandle<H>
+where
+    H: HandleNum,
+{
+    fn feed(&mut self) {
+        self.pet()
+    }
+}
+
+mod sealed {
+    pub trait HandleSet {}
+}
+
+/// A set of handles, one per enabled RR register
+///
+/// Implemented for tuples of one to eight handles, starting at RR0.
+pub trait HandleSet: sealed::HandleSet {
+    /// Bit mask of the RR registers in the set
+    const MASK: u32;
+
+    #[doc(hidden)]
+    fn new() -> Self;
+}
+
+macro_rules! handles {
+    ($($H:ident: $i:expr,)+) => {
+        $(
+            /// Watchdog reload register (type state)
+            pub struct $H;
+
+            impl HandleNum for $H {
+                const INDEX: usize = $i;
+            }
+        )+
+    }
+}
+
+handles!(
+    Hdl0: 0,
+    Hdl1: 1,
+    Hdl2: 2,
+    Hdl3: 3,
+    Hdl4: 4,
+    Hdl5: 5,
+    Hdl6: 6,
+    Hdl7: 7,
+);
+
+macro_rules! handle_set {
+    ($n:expr, ($($H:ident),+)) => {
+        impl sealed::HandleSet for ($(WatchdogHandle<$H>,)+) {}
+
+        impl HandleSet for ($(WatchdogHandle<$H>,)+) {
+            const MASK: u32 = (1 << $n) - 1;
+
+            fn new() -> Self {
+                ($(WatchdogHandle::<$H> { _handle: PhantomData },)+)
+            }
+        }
+    }
+}
+
+handle_set!(1, (Hdl0));
+handle_set!(2, (Hdl0, Hdl1));
+handle_set!(3, (Hdl0, Hdl1, Hdl2));
+handle_set!(4, (Hdl0, Hdl1, Hdl2, Hdl3));
+handle_set!(5, (Hdl0, Hdl1, Hdl2, Hdl3, Hdl4));
+handle_set!(6, (Hdl0, Hdl1, Hdl2, Hdl3, Hdl4, Hdl5));
+handle_set!(7, (Hdl0, Hdl1, Hdl2, Hdl3, Hdl4, Hdl5, Hdl6));
+handle_set!(8, (Hdl0, Hdl1, Hdl2, Hdl3, Hdl4, Hdl5, Hdl6, Hdl7));
+
+/// Error types associated with the WDT peripheral interface
+#[derive(Debug, PartialEq, Eq)]
+pub enum Error {
+    TimeoutOutOfRange,
+}